@@ -0,0 +1,382 @@
+//! Dumps parsed statements for people and tools: as an indented tree, one
+//! node per line with its fields and `@line:col`, or as a JSON array with one
+//! object per statement. Every JSON node has a `kind`, its `line`, `col` and
+//! `span`, and its fields by name.
+
+use std::fmt::Write;
+
+use crate::expr::{
+    BinaryOpTy, Expr, ExprKind, FunDecl, Literal, LogicalOp, Param, SourceLocation, Stmt, StmtKind,
+    Symbol, UnaryOpTy,
+};
+
+pub fn pretty(stmts: &[Stmt]) -> String {
+    let mut out = String::new();
+    for stmt in stmts {
+        write_tree(&mut out, &stmt_node(stmt), 0);
+    }
+    return out;
+}
+
+pub fn json(stmts: &[Stmt]) -> String {
+    let mut out = String::new();
+    let nodes: Vec<Node> = stmts.iter().map(stmt_node).collect();
+    write_json_list(&mut out, &nodes);
+    return out;
+}
+
+/// A statement or expression reduced to named fields, so both formats share
+/// one traversal of the tree.
+struct Node {
+    kind: &'static str,
+    location: SourceLocation,
+    fields: Vec<(&'static str, Field)>,
+}
+
+enum Field {
+    /// An optional part that isn't there, such as a missing `else`.
+    Absent,
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Text(String),
+    Node(Node),
+    List(Vec<Node>),
+}
+
+impl Node {
+    fn new(kind: &'static str, location: SourceLocation) -> Self {
+        return Self {
+            kind,
+            location,
+            fields: Vec::new(),
+        };
+    }
+
+    fn field(mut self, name: &'static str, field: Field) -> Self {
+        self.fields.push((name, field));
+        return self;
+    }
+
+    fn text(self, name: &'static str, text: &str) -> Self {
+        return self.field(name, Field::Text(text.to_string()));
+    }
+
+    fn symbol(self, name: &'static str, symbol: &Option<Symbol>) -> Self {
+        return match symbol {
+            Some(symbol) => self.text(name, &symbol.name),
+            None => self.field(name, Field::Absent),
+        };
+    }
+
+    fn expr(self, name: &'static str, expr: &Expr) -> Self {
+        return self.field(name, Field::Node(expr_node(expr)));
+    }
+
+    fn maybe_expr(self, name: &'static str, expr: &Option<Expr>) -> Self {
+        return match expr {
+            Some(expr) => self.expr(name, expr),
+            None => self.field(name, Field::Absent),
+        };
+    }
+
+    fn exprs(self, name: &'static str, exprs: &[Expr]) -> Self {
+        return self.field(name, Field::List(exprs.iter().map(expr_node).collect()));
+    }
+
+    fn stmt(self, name: &'static str, stmt: &Stmt) -> Self {
+        return self.field(name, Field::Node(stmt_node(stmt)));
+    }
+
+    fn stmts(self, name: &'static str, stmts: &[Stmt]) -> Self {
+        return self.field(name, Field::List(stmts.iter().map(stmt_node).collect()));
+    }
+
+    fn params(self, params: &[Param], rest: &Option<Symbol>) -> Self {
+        let params = params
+            .iter()
+            .map(|param| {
+                Node::new("Param", param.name.location())
+                    .text("name", &param.name.name)
+                    .maybe_expr("default", &param.default)
+            })
+            .collect();
+        return self
+            .field("params", Field::List(params))
+            .symbol("rest", rest);
+    }
+}
+
+fn function_node(function: &FunDecl, location: SourceLocation) -> Node {
+    return Node::new("Function", location)
+        .text("name", &function.name.name)
+        .params(&function.params, &function.rest)
+        .stmts("body", &function.body);
+}
+
+fn stmt_node(stmt: &Stmt) -> Node {
+    let node = |kind| Node::new(kind, stmt.location);
+    match &stmt.kind {
+        StmtKind::Expr(expr) => return node("Expression").expr("expr", expr),
+        StmtKind::Print(value) => return node("Print").expr("value", value),
+        StmtKind::VarDecl(name, initializer) => {
+            return node("Var")
+                .text("name", &name.name)
+                .maybe_expr("initializer", initializer)
+        }
+        StmtKind::Block(body) => return node("Block").stmts("body", body),
+        StmtKind::If(condition, then_branch, else_branch) => {
+            let node = node("If")
+                .expr("condition", condition)
+                .stmt("then", then_branch);
+            return match else_branch {
+                Some(else_branch) => node.stmt("else", else_branch),
+                None => node.field("else", Field::Absent),
+            };
+        }
+        StmtKind::While {
+            condition,
+            body,
+            increment,
+            label,
+        } => {
+            return node("While")
+                .symbol("label", label)
+                .expr("condition", condition)
+                .maybe_expr("increment", increment)
+                .stmt("body", body)
+        }
+        StmtKind::FunDecl(function) => return function_node(function, stmt.location),
+        StmtKind::ClassDecl(class) => {
+            let methods = class
+                .methods
+                .iter()
+                .map(|method| function_node(method, method.name.location()))
+                .collect();
+            return node("Class")
+                .text("name", &class.name.name)
+                .symbol("superclass", &class.superclass)
+                .field("methods", Field::List(methods));
+        }
+        StmtKind::Return(_, value) => return node("Return").maybe_expr("value", value),
+        StmtKind::Break(_, label) => return node("Break").symbol("label", label),
+        StmtKind::Continue(_, label) => return node("Continue").symbol("label", label),
+        StmtKind::Throw(_, value) => return node("Throw").expr("value", value),
+        StmtKind::Try {
+            body,
+            catch,
+            finally,
+        } => {
+            let catch = match catch {
+                Some((name, body)) => Field::Node(
+                    Node::new("Catch", name.location())
+                        .text("name", &name.name)
+                        .stmts("body", body),
+                ),
+                None => Field::Absent,
+            };
+            let node = node("Try").stmts("body", body).field("catch", catch);
+            return match finally {
+                Some(finally) => node.stmts("finally", finally),
+                None => node.field("finally", Field::Absent),
+            };
+        }
+    }
+}
+
+fn expr_node(expr: &Expr) -> Node {
+    let node = |kind| Node::new(kind, expr.location);
+    match &expr.kind {
+        ExprKind::Literal(literal) => {
+            let value = match literal {
+                Literal::Number(n) => Field::Number(*n),
+                Literal::String(s) => Field::Text(s.clone()),
+                Literal::True => Field::Bool(true),
+                Literal::False => Field::Bool(false),
+                Literal::Nil => Field::Nil,
+            };
+            return node("Literal").field("value", value);
+        }
+        ExprKind::This(_) => return node("This"),
+        ExprKind::Unary(op, right) => {
+            let op = match op.ty {
+                UnaryOpTy::Minus => "-",
+                UnaryOpTy::Bang => "!",
+            };
+            return node("Unary").text("op", op).expr("right", right);
+        }
+        ExprKind::Binary(left, op, right) => {
+            return node("Binary")
+                .expr("left", left)
+                .text("op", binary_op(op.ty))
+                .expr("right", right)
+        }
+        ExprKind::Logical(left, op, right) => {
+            let op = match op {
+                LogicalOp::And => "and",
+                LogicalOp::Or => "or",
+            };
+            return node("Logical")
+                .expr("left", left)
+                .text("op", op)
+                .expr("right", right);
+        }
+        ExprKind::Grouping(inner) => return node("Grouping").expr("expr", inner),
+        ExprKind::Variable(name) => return node("Variable").text("name", &name.name),
+        ExprKind::Assign(name, value) => {
+            return node("Assign").text("name", &name.name).expr("value", value)
+        }
+        ExprKind::Call(callee, _, args) => {
+            return node("Call").expr("callee", callee).exprs("arguments", args)
+        }
+        ExprKind::Get(object, name) => {
+            return node("Get").expr("object", object).text("name", &name.name)
+        }
+        ExprKind::Set(object, name, value) => {
+            return node("Set")
+                .expr("object", object)
+                .text("name", &name.name)
+                .expr("value", value)
+        }
+        ExprKind::Super(_, method) => return node("Super").text("method", &method.name),
+        ExprKind::List(elements) => return node("List").exprs("elements", elements),
+        ExprKind::Map(entries, _) => {
+            let entries = entries
+                .iter()
+                .map(|(key, value)| {
+                    Node::new("Entry", key.location.to(value.location.span))
+                        .expr("key", key)
+                        .expr("value", value)
+                })
+                .collect();
+            return node("Map").field("entries", Field::List(entries));
+        }
+        ExprKind::Subscript { value, slice, .. } => {
+            return node("Subscript").expr("object", value).expr("index", slice)
+        }
+        ExprKind::SetItem {
+            lhs, slice, rhs, ..
+        } => {
+            return node("SetItem")
+                .expr("object", lhs)
+                .expr("index", slice)
+                .expr("value", rhs)
+        }
+        ExprKind::Lambda(lambda) => {
+            return node("Lambda")
+                .params(&lambda.params, &lambda.rest)
+                .stmts("body", &lambda.body)
+        }
+        ExprKind::Spread(list) => return node("Spread").expr("value", list),
+    }
+}
+
+fn binary_op(ty: BinaryOpTy) -> &'static str {
+    return match ty {
+        BinaryOpTy::EqualEqual => "==",
+        BinaryOpTy::NotEqual => "!=",
+        BinaryOpTy::Less => "<",
+        BinaryOpTy::LessEqual => "<=",
+        BinaryOpTy::Greater => ">",
+        BinaryOpTy::GreaterEqual => ">=",
+        BinaryOpTy::Plus => "+",
+        BinaryOpTy::Minus => "-",
+        BinaryOpTy::Star => "*",
+        BinaryOpTy::Slash => "/",
+    };
+}
+
+/// Writes `node` on one line with its scalar fields, then each child under
+/// the name of the field holding it.
+fn write_tree(out: &mut String, node: &Node, indent: usize) {
+    let pad = " ".repeat(indent);
+    let _ = write!(out, "{pad}{}", node.kind);
+    for (name, field) in &node.fields {
+        match field {
+            Field::Bool(b) => {
+                let _ = write!(out, " {name}={b}");
+            }
+            Field::Number(n) => {
+                let _ = write!(out, " {name}={n}");
+            }
+            Field::Text(text) => {
+                let _ = write!(out, " {name}={text:?}");
+            }
+            Field::Nil => {
+                let _ = write!(out, " {name}=nil");
+            }
+            Field::Absent | Field::Node(_) | Field::List(_) => {}
+        }
+    }
+    let _ = writeln!(out, " @{}:{}", node.location.line, node.location.col);
+
+    for (name, field) in &node.fields {
+        match field {
+            Field::Node(child) => {
+                let _ = writeln!(out, "{pad}  {name}:");
+                write_tree(out, child, indent + 4);
+            }
+            Field::List(children) if !children.is_empty() => {
+                let _ = writeln!(out, "{pad}  {name}:");
+                for child in children {
+                    write_tree(out, child, indent + 4);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn write_json(out: &mut String, node: &Node) {
+    let span = node.location.span;
+    let _ = write!(
+        out,
+        "{{\"kind\":\"{}\",\"line\":{},\"col\":{},\"span\":{{\"file\":{},\"start\":{},\"end\":{}}}",
+        node.kind, node.location.line, node.location.col, span.file.0, span.start, span.end
+    );
+    for (name, field) in &node.fields {
+        let _ = write!(out, ",\"{name}\":");
+        match field {
+            Field::Absent | Field::Nil => out.push_str("null"),
+            Field::Bool(b) => {
+                let _ = write!(out, "{b}");
+            }
+            Field::Number(n) => {
+                let _ = write!(out, "{n}");
+            }
+            Field::Text(text) => write_json_string(out, text),
+            Field::Node(child) => write_json(out, child),
+            Field::List(children) => write_json_list(out, children),
+        }
+    }
+    out.push('}');
+}
+
+fn write_json_list(out: &mut String, nodes: &[Node]) {
+    out.push('[');
+    for (i, node) in nodes.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        write_json(out, node);
+    }
+    out.push(']');
+}
+
+fn write_json_string(out: &mut String, text: &str) {
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
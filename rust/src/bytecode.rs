@@ -0,0 +1,142 @@
+use std::{fmt, rc::Rc};
+
+use crate::expr::SourceLocation;
+
+/// A single VM instruction. Operands are carried inline: constant and name
+/// operands index into the chunk's constant table, jump operands are absolute
+/// instruction offsets within the same chunk.
+#[derive(Debug, Clone, Copy)]
+pub enum OpCode {
+    Constant(usize),
+    Nil,
+    True,
+    False,
+    /// Pushes the marker stored in variables declared without an initializer.
+    Undefined,
+    Pop,
+    GetLocal {
+        slot: usize,
+        name: usize,
+    },
+    SetLocal(usize),
+    GetGlobal(usize),
+    DefineGlobal(usize),
+    SetGlobal(usize),
+    GetUpvalue {
+        index: usize,
+        name: usize,
+    },
+    SetUpvalue(usize),
+    GetProperty(usize),
+    SetProperty(usize),
+    GetSuper(usize),
+    GetIndex,
+    SetIndex,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    Jump(usize),
+    /// Jumps when the value on top of the stack is falsey, leaving it there.
+    JumpIfFalse(usize),
+    Call(usize),
+    /// Calls the callee below the given number of lists, passing their
+    /// elements as the arguments.
+    CallSpread(usize),
+    /// Jumps to `target` when the call passed an argument for local `slot`,
+    /// skipping the code that computes the parameter's default.
+    DefaultArg {
+        slot: usize,
+        target: usize,
+    },
+    Closure(usize),
+    CloseUpvalue,
+    Return,
+    /// Builds a class from the `methods` closures on top of the stack. When
+    /// `superclass` is set the superclass sits right below them.
+    Class {
+        name: usize,
+        methods: usize,
+        superclass: bool,
+    },
+    List(usize),
+    Map(usize),
+    Throw,
+    /// Installs an exception handler that resumes at the given offset.
+    PushHandler(usize),
+    PopHandler,
+    /// Drops the error saved when a handler caught an exception.
+    PopException,
+    /// Raises the error saved when a handler caught an exception again.
+    Rethrow,
+}
+
+#[derive(Debug, Clone)]
+pub enum Constant {
+    Number(f64),
+    String(String),
+    Function(Rc<FunctionProto>),
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constant::Number(n) => write!(f, "{n}"),
+            Constant::String(s) => write!(f, "{s}"),
+            Constant::Function(function) => write!(f, "<fn {}>", function.name),
+        }
+    }
+}
+
+/// Where a closure finds a captured variable when it is created: a local slot
+/// of the enclosing function, or one of the enclosing closure's upvalues.
+#[derive(Debug, Clone, Copy)]
+pub struct UpvalueDesc {
+    pub is_local: bool,
+    pub index: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Chunk {
+    pub code: Vec<OpCode>,
+    /// Source location of each instruction, used for errors and traces.
+    pub locations: Vec<SourceLocation>,
+    pub constants: Vec<Constant>,
+}
+
+impl Chunk {
+    pub fn write(&mut self, op: OpCode, location: SourceLocation) -> usize {
+        self.code.push(op);
+        self.locations.push(location);
+        return self.code.len() - 1;
+    }
+
+    pub fn add_constant(&mut self, constant: Constant) -> usize {
+        self.constants.push(constant);
+        return self.constants.len() - 1;
+    }
+}
+
+/// A compiled function: its code plus what the VM needs to build closures
+/// over it.
+#[derive(Debug, Clone)]
+pub struct FunctionProto {
+    pub name: String,
+    /// Number of parameters, not counting a rest parameter.
+    pub arity: usize,
+    /// How many of the trailing parameters have defaults.
+    pub optional: usize,
+    /// Whether extra arguments are collected into a rest parameter.
+    pub rest: bool,
+    pub upvalues: Vec<UpvalueDesc>,
+    pub chunk: Chunk,
+}
@@ -0,0 +1,872 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{
+    bytecode::{Chunk, Constant, FunctionProto, OpCode, UpvalueDesc},
+    error::Error,
+    expr::{
+        BinaryOpTy, ClassDecl, Expr, ExprKind, Literal, LogicalOp, Param, SourceLocation, Stmt,
+        StmtKind, Symbol, UnaryOpTy,
+    },
+    resolver::keyword_symbol,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FunctionType {
+    Script,
+    Function,
+    Initializer,
+    Method,
+}
+
+struct Local {
+    name: String,
+    depth: usize,
+    is_captured: bool,
+}
+
+struct Loop {
+    label: Option<String>,
+    /// Number of locals live when the loop started; jumps out of the loop pop
+    /// everything above it.
+    locals: usize,
+    /// Length of the unwind stack when the loop started.
+    unwinds: usize,
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+/// Work a jump has to do when it leaves a `try` statement early.
+#[derive(Clone)]
+enum Unwind {
+    /// Uninstall the handler protecting a `try` or `catch` body.
+    Handler,
+    /// Run the `finally` block before leaving.
+    Finally(Vec<Stmt>),
+    /// Drop the error held while an exceptional `finally` block runs.
+    Exception,
+}
+
+struct FunctionState {
+    name: String,
+    function_type: FunctionType,
+    arity: usize,
+    chunk: Chunk,
+    upvalues: Vec<UpvalueDesc>,
+    locals: Vec<Local>,
+    scope_depth: usize,
+    loops: Vec<Loop>,
+    unwinds: Vec<Unwind>,
+    strings: HashMap<String, usize>,
+}
+
+impl FunctionState {
+    fn new(name: &str, function_type: FunctionType) -> Self {
+        // Slot zero holds the receiver for methods and the callee otherwise.
+        let slot_zero = match function_type {
+            FunctionType::Method | FunctionType::Initializer => "this",
+            _ => "",
+        };
+        return Self {
+            name: name.to_string(),
+            function_type,
+            arity: 0,
+            chunk: Chunk::default(),
+            upvalues: Vec::new(),
+            locals: vec![Local {
+                name: slot_zero.to_string(),
+                depth: 0,
+                is_captured: false,
+            }],
+            scope_depth: 0,
+            loops: Vec::new(),
+            unwinds: Vec::new(),
+            strings: HashMap::new(),
+        };
+    }
+}
+
+/// Compiles a resolved program into the function the VM runs as `<script>`.
+pub fn compile(stmts: &[Stmt]) -> Result<Rc<FunctionProto>, Error> {
+    let mut compiler = Compiler {
+        functions: vec![FunctionState::new("<script>", FunctionType::Script)],
+        location: SourceLocation::none(),
+    };
+    // Like `Interpreter::interpret`, the script returns the value of its last
+    // statement when that is an expression statement.
+    match stmts.split_last() {
+        Some((
+            Stmt {
+                kind: StmtKind::Expr(expr),
+                ..
+            },
+            rest,
+        )) => {
+            for stmt in rest {
+                compiler.statement(stmt)?;
+            }
+            compiler.expression(expr)?;
+        }
+        _ => {
+            for stmt in stmts {
+                compiler.statement(stmt)?;
+            }
+            compiler.emit(OpCode::Nil);
+        }
+    }
+    compiler.emit(OpCode::Return);
+    let script = compiler.functions.pop().unwrap();
+    return Ok(Rc::new(FunctionProto {
+        name: script.name,
+        arity: 0,
+        optional: 0,
+        rest: false,
+        upvalues: script.upvalues,
+        chunk: script.chunk,
+    }));
+}
+
+struct Compiler {
+    functions: Vec<FunctionState>,
+    /// Location recorded for the instructions emitted next.
+    location: SourceLocation,
+}
+
+impl Compiler {
+    fn current(&mut self) -> &mut FunctionState {
+        return self.functions.last_mut().unwrap();
+    }
+
+    fn emit(&mut self, op: OpCode) -> usize {
+        let location = self.location;
+        return self.current().chunk.write(op, location);
+    }
+
+    fn at(&mut self, location: SourceLocation) {
+        self.location = location;
+    }
+
+    fn constant(&mut self, constant: Constant) -> usize {
+        return self.current().chunk.add_constant(constant);
+    }
+
+    /// Interns `name` in the constant table of the current function.
+    fn string(&mut self, name: &str) -> usize {
+        if let Some(index) = self.current().strings.get(name) {
+            return *index;
+        }
+        let index = self.constant(Constant::String(name.to_string()));
+        self.current().strings.insert(name.to_string(), index);
+        return index;
+    }
+
+    fn emit_jump(&mut self, op: fn(usize) -> OpCode) -> usize {
+        return self.emit(op(usize::MAX));
+    }
+
+    /// Points the jump at `offset` to the next instruction.
+    fn patch_jump(&mut self, offset: usize) {
+        let target = self.current().chunk.code.len();
+        let code = &mut self.current().chunk.code;
+        code[offset] = match code[offset] {
+            OpCode::Jump(_) => OpCode::Jump(target),
+            OpCode::JumpIfFalse(_) => OpCode::JumpIfFalse(target),
+            OpCode::PushHandler(_) => OpCode::PushHandler(target),
+            OpCode::DefaultArg { slot, .. } => OpCode::DefaultArg { slot, target },
+            op => unreachable!("{op:?} is not a jump"),
+        };
+    }
+
+    fn statements(&mut self, stmts: &[Stmt]) -> Result<(), Error> {
+        for stmt in stmts {
+            self.statement(stmt)?;
+        }
+        return Ok(());
+    }
+
+    fn block(&mut self, stmts: &[Stmt]) -> Result<(), Error> {
+        self.begin_scope();
+        self.statements(stmts)?;
+        self.end_scope();
+        return Ok(());
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<(), Error> {
+        self.at(stmt.location);
+        match &stmt.kind {
+            StmtKind::Expr(expr) => {
+                self.expression(expr)?;
+                self.emit(OpCode::Pop);
+            }
+            StmtKind::Print(expr) => {
+                self.expression(expr)?;
+                self.emit(OpCode::Print);
+            }
+            StmtKind::VarDecl(name, initializer) => {
+                match initializer {
+                    Some(initializer) => self.expression(initializer)?,
+                    None => {
+                        self.emit(OpCode::Undefined);
+                    }
+                }
+                self.define_variable(name);
+            }
+            StmtKind::Block(stmts) => self.block(stmts)?,
+            StmtKind::If(condition, then_branch, else_branch) => {
+                self.expression(condition)?;
+                self.at(condition.location);
+                let then_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit(OpCode::Pop);
+                self.statement(then_branch)?;
+                let else_jump = self.emit_jump(OpCode::Jump);
+                self.patch_jump(then_jump);
+                self.emit(OpCode::Pop);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch)?;
+                }
+                self.patch_jump(else_jump);
+            }
+            StmtKind::While {
+                condition,
+                body,
+                increment,
+                label,
+            } => {
+                let start = self.current().chunk.code.len();
+                self.expression(condition)?;
+                self.at(condition.location);
+                let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit(OpCode::Pop);
+
+                let state = self.current();
+                let enclosing = Loop {
+                    label: label.as_ref().map(|label| label.name.clone()),
+                    locals: state.locals.len(),
+                    unwinds: state.unwinds.len(),
+                    breaks: Vec::new(),
+                    continues: Vec::new(),
+                };
+                state.loops.push(enclosing);
+                self.statement(body)?;
+                let finished = self.current().loops.pop().unwrap();
+
+                for jump in finished.continues {
+                    self.patch_jump(jump);
+                }
+                if let Some(increment) = increment {
+                    self.expression(increment)?;
+                    self.emit(OpCode::Pop);
+                }
+                self.emit(OpCode::Jump(start));
+                self.patch_jump(exit_jump);
+                self.emit(OpCode::Pop);
+                for jump in finished.breaks {
+                    self.patch_jump(jump);
+                }
+            }
+            StmtKind::Break(location, label) | StmtKind::Continue(location, label) => {
+                self.at(*location);
+                let label = label.as_ref().map(|label| label.name.as_str());
+                let index = self
+                    .current()
+                    .loops
+                    .iter()
+                    .rposition(|l| label.is_none() || l.label.as_deref() == label)
+                    .ok_or(Error::BreakNotInLoop {
+                        location: *location,
+                    })?;
+                let (locals, unwinds) = {
+                    let target = &self.current().loops[index];
+                    (target.locals, target.unwinds)
+                };
+                self.unwind(unwinds)?;
+                self.pop_locals(locals);
+                let jump = self.emit_jump(OpCode::Jump);
+                let target = &mut self.current().loops[index];
+                match &stmt.kind {
+                    StmtKind::Break(..) => target.breaks.push(jump),
+                    _ => target.continues.push(jump),
+                }
+            }
+            StmtKind::Return(location, value) => {
+                self.at(*location);
+                if self.current().function_type == FunctionType::Initializer {
+                    self.this_value()?;
+                } else {
+                    match value {
+                        Some(value) => self.expression(value)?,
+                        None => {
+                            self.emit(OpCode::Nil);
+                        }
+                    }
+                }
+                // Keep the return value out of the way of any `finally` block
+                // that runs on the way out.
+                self.add_local("");
+                self.unwind(0)?;
+                self.current().locals.pop();
+                self.emit(OpCode::Return);
+            }
+            StmtKind::FunDecl(fun_decl) => {
+                if self.current().scope_depth > 0 {
+                    // Declare first so the function can refer to itself.
+                    self.add_local(&fun_decl.name.name);
+                }
+                self.function(
+                    &fun_decl.name.name,
+                    &fun_decl.params,
+                    fun_decl.rest.as_ref(),
+                    &fun_decl.body,
+                    FunctionType::Function,
+                )?;
+                if self.current().scope_depth == 0 {
+                    self.at(fun_decl.name.location());
+                    let name = self.string(&fun_decl.name.name);
+                    self.emit(OpCode::DefineGlobal(name));
+                }
+            }
+            StmtKind::ClassDecl(class_decl) => self.class_declaration(class_decl)?,
+            StmtKind::Throw(location, value) => {
+                self.expression(value)?;
+                self.at(*location);
+                self.emit(OpCode::Throw);
+            }
+            StmtKind::Try {
+                body,
+                catch,
+                finally,
+            } => self.try_statement(body, catch, finally)?,
+        }
+        return Ok(());
+    }
+
+    fn class_declaration(&mut self, class_decl: &ClassDecl) -> Result<(), Error> {
+        let name = &class_decl.name;
+        let is_local = self.current().scope_depth > 0;
+        if is_local {
+            // Reserve the slot up front so methods can capture the class.
+            self.emit(OpCode::Nil);
+            self.add_local(&name.name);
+        }
+        let slot = self.current().locals.len() - 1;
+
+        self.begin_scope();
+        if let Some(superclass) = &class_decl.superclass {
+            if superclass.name == name.name {
+                return Err(Error::InheritFromSelf {
+                    location: superclass.location(),
+                });
+            }
+            self.variable(superclass)?;
+            self.add_local("super");
+        }
+        for method in &class_decl.methods {
+            let function_type = if method.name.name == "init" {
+                FunctionType::Initializer
+            } else {
+                FunctionType::Method
+            };
+            self.function(
+                &method.name.name,
+                &method.params,
+                method.rest.as_ref(),
+                &method.body,
+                function_type,
+            )?;
+        }
+
+        match &class_decl.superclass {
+            Some(superclass) => self.at(superclass.location()),
+            None => self.at(name.location()),
+        }
+        let name_constant = self.string(&name.name);
+        self.emit(OpCode::Class {
+            name: name_constant,
+            methods: class_decl.methods.len(),
+            superclass: class_decl.superclass.is_some(),
+        });
+        self.at(name.location());
+        if is_local {
+            self.emit(OpCode::SetLocal(slot));
+            self.emit(OpCode::Pop);
+        } else {
+            self.emit(OpCode::DefineGlobal(name_constant));
+        }
+        self.end_scope();
+        return Ok(());
+    }
+
+    /// Compiles a `try` statement. The handler protecting the body jumps to
+    /// the `catch` block, or straight to a copy of the `finally` block that
+    /// raises the error again. A `finally` block is also copied onto the
+    /// normal exit path and in front of every jump that leaves the statement.
+    fn try_statement(
+        &mut self,
+        body: &[Stmt],
+        catch: &Option<(Symbol, Vec<Stmt>)>,
+        finally: &Option<Vec<Stmt>>,
+    ) -> Result<(), Error> {
+        if let Some(finally_body) = finally {
+            self.current()
+                .unwinds
+                .push(Unwind::Finally(finally_body.clone()));
+        }
+        let handler = self.push_handler();
+        self.block(body)?;
+        self.pop_handler();
+        let mut exits = vec![self.emit_jump(OpCode::Jump)];
+        self.patch_jump(handler);
+
+        // The caught exception value is on top of the stack here.
+        let mut pending = Some(1);
+        if let Some((name, catch_body)) = catch {
+            self.emit(OpCode::PopException);
+            let handler = finally.as_ref().map(|_| self.push_handler());
+            self.begin_scope();
+            self.add_local(&name.name);
+            self.statements(catch_body)?;
+            self.end_scope();
+            pending = None;
+            if let Some(handler) = handler {
+                self.pop_handler();
+                exits.push(self.emit_jump(OpCode::Jump));
+                self.patch_jump(handler);
+                // The handler leaves the value the catch received below the
+                // new exception.
+                pending = Some(2);
+            }
+        }
+
+        if let Some(finally_body) = finally {
+            self.current().unwinds.pop();
+            if let Some(pending) = pending {
+                self.begin_scope();
+                for _ in 0..pending {
+                    self.add_local("");
+                }
+                self.current().unwinds.push(Unwind::Exception);
+                self.block(finally_body)?;
+                self.current().unwinds.pop();
+                self.emit(OpCode::Rethrow);
+                // Rethrow never falls through, so the slots are dropped
+                // without emitting pops for them.
+                let state = self.current();
+                state.scope_depth -= 1;
+                let depth = state.scope_depth;
+                state.locals.retain(|local| local.depth <= depth);
+            }
+        }
+        for exit in exits {
+            self.patch_jump(exit);
+        }
+        if let Some(finally_body) = finally {
+            self.block(finally_body)?;
+        }
+        return Ok(());
+    }
+
+    fn push_handler(&mut self) -> usize {
+        self.current().unwinds.push(Unwind::Handler);
+        return self.emit_jump(OpCode::PushHandler);
+    }
+
+    fn pop_handler(&mut self) {
+        self.current().unwinds.pop();
+        self.emit(OpCode::PopHandler);
+    }
+
+    /// Emits what a jump must do to leave every `try` statement entered since
+    /// the unwind stack had `depth` entries, innermost first.
+    fn unwind(&mut self, depth: usize) -> Result<(), Error> {
+        for index in (depth..self.current().unwinds.len()).rev() {
+            match self.current().unwinds[index].clone() {
+                Unwind::Handler => {
+                    self.emit(OpCode::PopHandler);
+                }
+                Unwind::Exception => {
+                    self.emit(OpCode::PopException);
+                }
+                Unwind::Finally(finally_body) => {
+                    // The copied block runs outside the statement it belongs to.
+                    let inner = self.current().unwinds.split_off(index);
+                    let location = self.location;
+                    self.block(&finally_body)?;
+                    self.location = location;
+                    self.current().unwinds.extend(inner);
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    /// Emits pops for the locals above `count` without forgetting them, for
+    /// jumps that leave their scopes.
+    fn pop_locals(&mut self, count: usize) {
+        let captured: Vec<bool> = self.current().locals[count..]
+            .iter()
+            .rev()
+            .map(|local| local.is_captured)
+            .collect();
+        for is_captured in captured {
+            if is_captured {
+                self.emit(OpCode::CloseUpvalue);
+            } else {
+                self.emit(OpCode::Pop);
+            }
+        }
+    }
+
+    fn function(
+        &mut self,
+        name: &str,
+        params: &[Param],
+        rest: Option<&Symbol>,
+        body: &[Stmt],
+        function_type: FunctionType,
+    ) -> Result<(), Error> {
+        let location = self.location;
+        let mut state = FunctionState::new(name, function_type);
+        state.arity = params.len();
+        self.functions.push(state);
+        self.begin_scope();
+        for (i, param) in params.iter().enumerate() {
+            // The default is compiled before the parameter is declared so it
+            // only sees the parameters before it.
+            if let Some(default) = &param.default {
+                let slot = i + 1;
+                self.at(param.name.location());
+                let skip = self.emit(OpCode::DefaultArg {
+                    slot,
+                    target: usize::MAX,
+                });
+                self.expression(default)?;
+                self.emit(OpCode::SetLocal(slot));
+                self.emit(OpCode::Pop);
+                self.patch_jump(skip);
+            }
+            self.add_local(&param.name.name);
+        }
+        if let Some(rest) = rest {
+            self.add_local(&rest.name);
+        }
+        self.statements(body)?;
+        if function_type == FunctionType::Initializer {
+            self.this_value()?;
+        } else {
+            self.emit(OpCode::Nil);
+        }
+        self.emit(OpCode::Return);
+
+        let state = self.functions.pop().unwrap();
+        self.location = location;
+        let proto = FunctionProto {
+            name: state.name,
+            arity: state.arity,
+            optional: params.iter().filter(|p| p.default.is_some()).count(),
+            rest: rest.is_some(),
+            upvalues: state.upvalues,
+            chunk: state.chunk,
+        };
+        let index = self.constant(Constant::Function(Rc::new(proto)));
+        self.emit(OpCode::Closure(index));
+        return Ok(());
+    }
+
+    fn expression(&mut self, expr: &Expr) -> Result<(), Error> {
+        self.at(expr.location);
+        match &expr.kind {
+            ExprKind::Literal(literal) => match literal {
+                Literal::Number(n) => {
+                    let index = self.constant(Constant::Number(*n));
+                    self.emit(OpCode::Constant(index));
+                }
+                Literal::String(s) => {
+                    let index = self.string(s);
+                    self.emit(OpCode::Constant(index));
+                }
+                Literal::True => {
+                    self.emit(OpCode::True);
+                }
+                Literal::False => {
+                    self.emit(OpCode::False);
+                }
+                Literal::Nil => {
+                    self.emit(OpCode::Nil);
+                }
+            },
+            ExprKind::Grouping(expr) => self.expression(expr)?,
+            ExprKind::Unary(op, right) => {
+                self.expression(right)?;
+                self.at(op.location());
+                match op.ty {
+                    UnaryOpTy::Minus => self.emit(OpCode::Negate),
+                    UnaryOpTy::Bang => self.emit(OpCode::Not),
+                };
+            }
+            ExprKind::Binary(left, op, right) => {
+                self.expression(left)?;
+                self.expression(right)?;
+                self.at(op.location());
+                self.emit(match op.ty {
+                    BinaryOpTy::EqualEqual => OpCode::Equal,
+                    BinaryOpTy::NotEqual => OpCode::NotEqual,
+                    BinaryOpTy::Less => OpCode::Less,
+                    BinaryOpTy::LessEqual => OpCode::LessEqual,
+                    BinaryOpTy::Greater => OpCode::Greater,
+                    BinaryOpTy::GreaterEqual => OpCode::GreaterEqual,
+                    BinaryOpTy::Plus => OpCode::Add,
+                    BinaryOpTy::Minus => OpCode::Subtract,
+                    BinaryOpTy::Star => OpCode::Multiply,
+                    BinaryOpTy::Slash => OpCode::Divide,
+                });
+            }
+            ExprKind::Logical(left, op, right) => {
+                self.expression(left)?;
+                self.at(left.location);
+                match op {
+                    LogicalOp::And => {
+                        let end_jump = self.emit_jump(OpCode::JumpIfFalse);
+                        self.emit(OpCode::Pop);
+                        self.expression(right)?;
+                        self.patch_jump(end_jump);
+                    }
+                    LogicalOp::Or => {
+                        let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+                        let end_jump = self.emit_jump(OpCode::Jump);
+                        self.patch_jump(else_jump);
+                        self.emit(OpCode::Pop);
+                        self.expression(right)?;
+                        self.patch_jump(end_jump);
+                    }
+                }
+            }
+            ExprKind::Variable(name) => self.variable(name)?,
+            ExprKind::Assign(name, value) => {
+                self.expression(value)?;
+                self.at(name.location());
+                let depth = self.functions.len() - 1;
+                if let Some(slot) = self.resolve_local(depth, &name.name) {
+                    self.emit(OpCode::SetLocal(slot));
+                } else if let Some(index) = self.resolve_upvalue(depth, &name.name) {
+                    self.emit(OpCode::SetUpvalue(index));
+                } else {
+                    let name = self.string(&name.name);
+                    self.emit(OpCode::SetGlobal(name));
+                }
+            }
+            ExprKind::Call(callee, location, args) => {
+                self.expression(callee)?;
+                if !args
+                    .iter()
+                    .any(|arg| matches!(arg.kind, ExprKind::Spread(_)))
+                {
+                    for arg in args {
+                        self.expression(arg)?;
+                    }
+                    self.at(*location);
+                    self.emit(OpCode::Call(args.len()));
+                    return Ok(());
+                }
+                // Gather each run of plain arguments into a list so that every
+                // part the call spreads is a list.
+                let mut parts = 0;
+                let mut run = 0;
+                for arg in args {
+                    if let ExprKind::Spread(list) = &arg.kind {
+                        if run > 0 {
+                            self.emit(OpCode::List(run));
+                            parts += 1;
+                            run = 0;
+                        }
+                        self.expression(list)?;
+                        parts += 1;
+                    } else {
+                        self.expression(arg)?;
+                        run += 1;
+                    }
+                }
+                if run > 0 {
+                    self.emit(OpCode::List(run));
+                    parts += 1;
+                }
+                self.at(*location);
+                self.emit(OpCode::CallSpread(parts));
+            }
+            ExprKind::Get(object, name) => {
+                self.expression(object)?;
+                self.at(name.location());
+                let name = self.string(&name.name);
+                self.emit(OpCode::GetProperty(name));
+            }
+            ExprKind::Set(object, name, value) => {
+                self.expression(object)?;
+                self.expression(value)?;
+                self.at(name.location());
+                let name = self.string(&name.name);
+                self.emit(OpCode::SetProperty(name));
+            }
+            ExprKind::This(location) => {
+                self.at(*location);
+                self.this_value()?;
+            }
+            ExprKind::Super(location, method) => {
+                self.at(*location);
+                self.this_value()?;
+                self.variable(&keyword_symbol("super", location))?;
+                self.at(method.location());
+                let name = self.string(&method.name);
+                self.emit(OpCode::GetSuper(name));
+            }
+            ExprKind::List(elements) => {
+                for element in elements {
+                    self.expression(element)?;
+                }
+                self.at(expr.location);
+                self.emit(OpCode::List(elements.len()));
+            }
+            ExprKind::Map(entries, location) => {
+                for (key, value) in entries {
+                    self.expression(key)?;
+                    self.expression(value)?;
+                }
+                self.at(*location);
+                self.emit(OpCode::Map(entries.len()));
+            }
+            ExprKind::Subscript {
+                value,
+                slice,
+                source_location,
+            } => {
+                self.expression(value)?;
+                self.expression(slice)?;
+                self.at(*source_location);
+                self.emit(OpCode::GetIndex);
+            }
+            ExprKind::SetItem {
+                lhs,
+                slice,
+                rhs,
+                source_location,
+            } => {
+                self.expression(lhs)?;
+                self.expression(slice)?;
+                self.expression(rhs)?;
+                self.at(*source_location);
+                self.emit(OpCode::SetIndex);
+            }
+            ExprKind::Spread(_) => unreachable!("the parser only allows spreads in call arguments"),
+            ExprKind::Lambda(lambda_decl) => {
+                self.at(lambda_decl.keyword);
+                self.function(
+                    "lambda",
+                    &lambda_decl.params,
+                    lambda_decl.rest.as_ref(),
+                    &lambda_decl.body,
+                    FunctionType::Function,
+                )?;
+            }
+        }
+        return Ok(());
+    }
+
+    fn variable(&mut self, name: &Symbol) -> Result<(), Error> {
+        self.at(name.location());
+        let depth = self.functions.len() - 1;
+        let name_constant = self.string(&name.name);
+        if let Some(slot) = self.resolve_local(depth, &name.name) {
+            self.emit(OpCode::GetLocal {
+                slot,
+                name: name_constant,
+            });
+        } else if let Some(index) = self.resolve_upvalue(depth, &name.name) {
+            self.emit(OpCode::GetUpvalue {
+                index,
+                name: name_constant,
+            });
+        } else {
+            self.emit(OpCode::GetGlobal(name_constant));
+        }
+        return Ok(());
+    }
+
+    fn this_value(&mut self) -> Result<(), Error> {
+        let location = self.location;
+        return self.variable(&keyword_symbol("this", &location));
+    }
+
+    fn define_variable(&mut self, name: &Symbol) {
+        if self.current().scope_depth > 0 {
+            // The value already sits in the slot the new local refers to.
+            self.add_local(&name.name);
+            return;
+        }
+        self.at(name.location());
+        let name = self.string(&name.name);
+        self.emit(OpCode::DefineGlobal(name));
+    }
+
+    fn add_local(&mut self, name: &str) {
+        let state = self.current();
+        let depth = state.scope_depth;
+        state.locals.push(Local {
+            name: name.to_string(),
+            depth,
+            is_captured: false,
+        });
+    }
+
+    fn resolve_local(&self, depth: usize, name: &str) -> Option<usize> {
+        return self.functions[depth]
+            .locals
+            .iter()
+            .rposition(|local| local.name == name);
+    }
+
+    fn resolve_upvalue(&mut self, depth: usize, name: &str) -> Option<usize> {
+        if depth == 0 {
+            return None;
+        }
+        if let Some(slot) = self.resolve_local(depth - 1, name) {
+            self.functions[depth - 1].locals[slot].is_captured = true;
+            return Some(self.add_upvalue(depth, true, slot));
+        }
+        let index = self.resolve_upvalue(depth - 1, name)?;
+        return Some(self.add_upvalue(depth, false, index));
+    }
+
+    fn add_upvalue(&mut self, depth: usize, is_local: bool, index: usize) -> usize {
+        let upvalues = &mut self.functions[depth].upvalues;
+        if let Some(existing) = upvalues
+            .iter()
+            .position(|up| up.is_local == is_local && up.index == index)
+        {
+            return existing;
+        }
+        upvalues.push(UpvalueDesc { is_local, index });
+        return upvalues.len() - 1;
+    }
+
+    fn begin_scope(&mut self) {
+        self.current().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        let state = self.current();
+        state.scope_depth -= 1;
+        let depth = state.scope_depth;
+        let mut ops = Vec::new();
+        while let Some(local) = state.locals.last() {
+            if local.depth <= depth {
+                break;
+            }
+            ops.push(if local.is_captured {
+                OpCode::CloseUpvalue
+            } else {
+                OpCode::Pop
+            });
+            state.locals.pop();
+        }
+        for op in ops {
+            self.emit(op);
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use std::fmt::Write;
+
+use crate::bytecode::{Chunk, Constant, FunctionProto, OpCode};
+
+/// Disassembles `function` followed by every function nested in it, in the
+/// order their constants appear.
+pub fn disassemble_function(function: &FunctionProto) -> String {
+    let mut out = disassemble_chunk(&function.chunk, &function.name);
+    for constant in &function.chunk.constants {
+        if let Constant::Function(nested) = constant {
+            out.push('\n');
+            out.push_str(&disassemble_function(nested));
+        }
+    }
+    return out;
+}
+
+pub fn disassemble_chunk(chunk: &Chunk, name: &str) -> String {
+    let mut out = format!("== {name} ==\n");
+    for offset in 0..chunk.code.len() {
+        out.push_str(&disassemble_instruction(chunk, offset));
+    }
+    return out;
+}
+
+/// One line per instruction: offset, source line (or `|` when unchanged),
+/// opcode and operands. Closures get an extra line per captured variable.
+pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> String {
+    let mut out = format!("{offset:04} ");
+    let line = chunk.locations[offset].line;
+    if offset > 0 && line == chunk.locations[offset - 1].line {
+        out.push_str("   | ");
+    } else {
+        let _ = write!(out, "{line:4} ");
+    }
+
+    match chunk.code[offset] {
+        OpCode::Constant(index) => constant_instruction(&mut out, "OP_CONSTANT", chunk, index),
+        OpCode::Nil => simple_instruction(&mut out, "OP_NIL"),
+        OpCode::True => simple_instruction(&mut out, "OP_TRUE"),
+        OpCode::False => simple_instruction(&mut out, "OP_FALSE"),
+        OpCode::Undefined => simple_instruction(&mut out, "OP_UNDEFINED"),
+        OpCode::Pop => simple_instruction(&mut out, "OP_POP"),
+        OpCode::GetLocal { slot, name } => {
+            let _ = writeln!(
+                out,
+                "{:<16} {slot:4} '{}'",
+                "OP_GET_LOCAL", chunk.constants[name]
+            );
+        }
+        OpCode::SetLocal(slot) => byte_instruction(&mut out, "OP_SET_LOCAL", slot),
+        OpCode::GetGlobal(name) => constant_instruction(&mut out, "OP_GET_GLOBAL", chunk, name),
+        OpCode::DefineGlobal(name) => {
+            constant_instruction(&mut out, "OP_DEFINE_GLOBAL", chunk, name)
+        }
+        OpCode::SetGlobal(name) => constant_instruction(&mut out, "OP_SET_GLOBAL", chunk, name),
+        OpCode::GetUpvalue { index, name } => {
+            let _ = writeln!(
+                out,
+                "{:<16} {index:4} '{}'",
+                "OP_GET_UPVALUE", chunk.constants[name]
+            );
+        }
+        OpCode::SetUpvalue(index) => byte_instruction(&mut out, "OP_SET_UPVALUE", index),
+        OpCode::GetProperty(name) => constant_instruction(&mut out, "OP_GET_PROPERTY", chunk, name),
+        OpCode::SetProperty(name) => constant_instruction(&mut out, "OP_SET_PROPERTY", chunk, name),
+        OpCode::GetSuper(name) => constant_instruction(&mut out, "OP_GET_SUPER", chunk, name),
+        OpCode::GetIndex => simple_instruction(&mut out, "OP_GET_INDEX"),
+        OpCode::SetIndex => simple_instruction(&mut out, "OP_SET_INDEX"),
+        OpCode::Equal => simple_instruction(&mut out, "OP_EQUAL"),
+        OpCode::NotEqual => simple_instruction(&mut out, "OP_NOT_EQUAL"),
+        OpCode::Greater => simple_instruction(&mut out, "OP_GREATER"),
+        OpCode::GreaterEqual => simple_instruction(&mut out, "OP_GREATER_EQUAL"),
+        OpCode::Less => simple_instruction(&mut out, "OP_LESS"),
+        OpCode::LessEqual => simple_instruction(&mut out, "OP_LESS_EQUAL"),
+        OpCode::Add => simple_instruction(&mut out, "OP_ADD"),
+        OpCode::Subtract => simple_instruction(&mut out, "OP_SUBTRACT"),
+        OpCode::Multiply => simple_instruction(&mut out, "OP_MULTIPLY"),
+        OpCode::Divide => simple_instruction(&mut out, "OP_DIVIDE"),
+        OpCode::Not => simple_instruction(&mut out, "OP_NOT"),
+        OpCode::Negate => simple_instruction(&mut out, "OP_NEGATE"),
+        OpCode::Print => simple_instruction(&mut out, "OP_PRINT"),
+        OpCode::Jump(target) => jump_instruction(&mut out, "OP_JUMP", offset, target),
+        OpCode::JumpIfFalse(target) => {
+            jump_instruction(&mut out, "OP_JUMP_IF_FALSE", offset, target)
+        }
+        OpCode::Call(arg_count) => byte_instruction(&mut out, "OP_CALL", arg_count),
+        OpCode::CallSpread(parts) => byte_instruction(&mut out, "OP_CALL_SPREAD", parts),
+        OpCode::DefaultArg { slot, target } => {
+            let _ = writeln!(out, "{:<16} {slot:4} -> {target}", "OP_DEFAULT_ARG");
+        }
+        OpCode::Closure(index) => {
+            constant_instruction(&mut out, "OP_CLOSURE", chunk, index);
+            if let Constant::Function(function) = &chunk.constants[index] {
+                for upvalue in &function.upvalues {
+                    let kind = if upvalue.is_local { "local" } else { "upvalue" };
+                    let _ = writeln!(
+                        out,
+                        "{offset:04}    |                     {kind} {}",
+                        upvalue.index
+                    );
+                }
+            }
+        }
+        OpCode::CloseUpvalue => simple_instruction(&mut out, "OP_CLOSE_UPVALUE"),
+        OpCode::Return => simple_instruction(&mut out, "OP_RETURN"),
+        OpCode::Class {
+            name,
+            methods,
+            superclass,
+        } => {
+            let _ = write!(
+                out,
+                "{:<16} {name:4} '{}' {methods} methods",
+                "OP_CLASS", chunk.constants[name]
+            );
+            if superclass {
+                out.push_str(", superclass");
+            }
+            out.push('\n');
+        }
+        OpCode::List(count) => byte_instruction(&mut out, "OP_LIST", count),
+        OpCode::Map(count) => byte_instruction(&mut out, "OP_MAP", count),
+        OpCode::Throw => simple_instruction(&mut out, "OP_THROW"),
+        OpCode::PushHandler(target) => {
+            jump_instruction(&mut out, "OP_PUSH_HANDLER", offset, target)
+        }
+        OpCode::PopHandler => simple_instruction(&mut out, "OP_POP_HANDLER"),
+        OpCode::PopException => simple_instruction(&mut out, "OP_POP_EXCEPTION"),
+        OpCode::Rethrow => simple_instruction(&mut out, "OP_RETHROW"),
+    }
+    return out;
+}
+
+fn simple_instruction(out: &mut String, name: &str) {
+    let _ = writeln!(out, "{name}");
+}
+
+fn byte_instruction(out: &mut String, name: &str, operand: usize) {
+    let _ = writeln!(out, "{name:<16} {operand:4}");
+}
+
+fn constant_instruction(out: &mut String, name: &str, chunk: &Chunk, index: usize) {
+    let _ = writeln!(out, "{name:<16} {index:4} '{}'", chunk.constants[index]);
+}
+
+fn jump_instruction(out: &mut String, name: &str, offset: usize, target: usize) {
+    let _ = writeln!(out, "{name:<16} {offset:4} -> {target}");
+}
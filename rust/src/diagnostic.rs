@@ -0,0 +1,354 @@
+//! Compiler-style error reports that quote the offending source line:
+//!
+//! ```text
+//! error[E0302]: Undefined variable `y`
+//!  --> script.lox:2:11
+//!   |
+//! 2 | print x + y;
+//!   |           ^
+//!   |
+//!   = help: give it a value first, e.g. `var y = nil;`
+//!   = note: at <script> (line 2)
+//! ```
+
+use std::fmt::Write;
+use std::io::IsTerminal;
+
+use crate::{
+    error::{trace_lines, Error},
+    expr::{SourceLocation, Span},
+    scanner,
+};
+
+pub struct Diagnostic {
+    /// Identifies the kind of error, e.g. `E0302`.
+    pub code: &'static str,
+    pub message: String,
+    /// Line of the error, or 0 when it has no location.
+    pub line: usize,
+    /// 1-based column of the first character the error is about, or 0 or
+    /// less when only the line is known.
+    pub col: i64,
+    /// The source the error is about, which is underlined. Empty when only
+    /// the line and column are known; one character is underlined then.
+    pub span: Span,
+    /// Short text printed after the underline.
+    pub label: Option<String>,
+    pub help: Option<String>,
+    /// Extra lines printed at the end, such as a traceback.
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    fn new(code: &'static str, message: String, line: usize, col: i64) -> Self {
+        return Self {
+            code,
+            message,
+            line,
+            col,
+            span: Span::default(),
+            label: None,
+            help: None,
+            notes: Vec::new(),
+        };
+    }
+
+    /// A report about the source at `location`.
+    fn at(code: &'static str, message: String, location: SourceLocation) -> Self {
+        return Self {
+            span: location.span,
+            ..Self::new(code, message, location.line, location.col)
+        };
+    }
+
+    fn label(mut self, label: &str) -> Self {
+        self.label = Some(label.to_string());
+        return self;
+    }
+
+    fn help(mut self, help: String) -> Self {
+        self.help = Some(help);
+        return self;
+    }
+
+    /// Renders the report. `source` is the text `file` was loaded from; when
+    /// it is not available only the location is shown.
+    pub fn render(&self, file: &str, source: Option<&str>, color: bool) -> String {
+        let style = Style { color };
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{}: {}",
+            style.paint(&format!("error[{}]", self.code), RED),
+            style.paint(&self.message, BOLD)
+        );
+
+        let line_text = source.and_then(|source| {
+            let text = source.lines().nth(self.line.wrapping_sub(1))?;
+            return Some((source, text));
+        });
+        let gutter = " ".repeat(self.line.to_string().len());
+        let arrow = style.paint("-->", BLUE);
+        match (self.line, self.col) {
+            (0, _) => {
+                let _ = writeln!(out, "{gutter}{arrow} {file}");
+            }
+            (line, col) if col > 0 => {
+                let _ = writeln!(out, "{gutter}{arrow} {file}:{line}:{col}");
+            }
+            (line, _) => {
+                let _ = writeln!(out, "{gutter}{arrow} {file}:{line}");
+            }
+        }
+
+        let bar = style.paint("|", BLUE);
+        if let Some((source, text)) = line_text {
+            let _ = writeln!(out, "{gutter} {bar}");
+            let number = style.paint(&self.line.to_string(), BLUE);
+            let _ = writeln!(out, "{number} {bar} {text}");
+            if self.col > 0 {
+                // Offsets count bytes, but the underline has to line up with
+                // characters.
+                let (start, end) = self.marked(source, text);
+                let padding: String = text[..start]
+                    .chars()
+                    .map(|c| if c == '\t' { '\t' } else { ' ' })
+                    .collect();
+                let width = text[start..end].chars().count().max(1);
+                let mut marker = "^".repeat(width);
+                if let Some(label) = &self.label {
+                    marker = format!("{marker} {label}");
+                }
+                let _ = writeln!(out, "{gutter} {bar} {padding}{}", style.paint(&marker, RED));
+            }
+        }
+
+        if self.help.is_some() || !self.notes.is_empty() {
+            let _ = writeln!(out, "{gutter} {bar}");
+        }
+        if let Some(help) = &self.help {
+            let _ = writeln!(out, "{gutter} {} {help}", style.paint("= help:", CYAN));
+        }
+        for note in &self.notes {
+            let _ = writeln!(out, "{gutter} {} {note}", style.paint("= note:", CYAN));
+        }
+        return out;
+    }
+
+    /// The bytes of `text`, line `self.line` of `source`, to underline: the
+    /// part of the span on that line, or the character at the column when
+    /// there is no span. Both ends fall on character boundaries, widened to
+    /// the whole character when an offset lands inside one.
+    fn marked(&self, source: &str, text: &str) -> (usize, usize) {
+        let (mut start, mut end) = self.marked_bytes(source, text);
+        while !text.is_char_boundary(start) {
+            start -= 1;
+        }
+        while !text.is_char_boundary(end) {
+            end += 1;
+        }
+        return (start, end);
+    }
+
+    fn marked_bytes(&self, source: &str, text: &str) -> (usize, usize) {
+        let line_start: usize = source
+            .split_inclusive('\n')
+            .take(self.line - 1)
+            .map(str::len)
+            .sum();
+        let span = self.span;
+        if span.start < span.end && (line_start..=line_start + text.len()).contains(&span.start) {
+            let start = span.start - line_start;
+            let end = (span.end - line_start).min(text.len());
+            return (start, end);
+        }
+        let start = (self.col as usize - 1).min(text.len());
+        return (start, (start + 1).min(text.len()));
+    }
+}
+
+/// Whether reports printed to stderr should be coloured.
+pub fn use_color() -> bool {
+    return std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+}
+
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const CYAN: &str = "\x1b[1;36m";
+const BOLD: &str = "\x1b[1m";
+
+struct Style {
+    color: bool,
+}
+
+impl Style {
+    fn paint(&self, text: &str, code: &str) -> String {
+        if !self.color {
+            return text.to_string();
+        }
+        return format!("{code}{text}\x1b[0m");
+    }
+}
+
+impl From<&scanner::Error> for Diagnostic {
+    fn from(error: &scanner::Error) -> Self {
+        return Diagnostic::new("E0001", error.what.clone(), error.line, error.col);
+    }
+}
+
+impl From<&Error> for Diagnostic {
+    fn from(error: &Error) -> Self {
+        match error {
+            Error::UnexpectedToken(token) => {
+                let lexeme = String::from_utf8_lossy(&token.lexeme);
+                return Diagnostic::at(
+                    "E0101",
+                    format!("Unexpected token `{lexeme}`"),
+                    token.location(),
+                );
+            }
+            Error::TokenMismatch {
+                expected,
+                found,
+                maybe_on_err_string,
+            } => {
+                let message = match maybe_on_err_string {
+                    Some(message) => message.clone(),
+                    None => format!("Expected {expected:?}"),
+                };
+                let label = match found.ty {
+                    scanner::TokenType::Eof => "found end of file".to_string(),
+                    _ => format!("found `{}`", String::from_utf8_lossy(&found.lexeme)),
+                };
+                return Diagnostic::at("E0102", message, found.location()).label(&label);
+            }
+            Error::MaxParamsExceeded { kind, location } => Diagnostic::at(
+                "E0103",
+                format!("Can't have more than 255 parameters in a {kind}"),
+                *location,
+            ),
+            Error::TooManyArguments { location } => Diagnostic::at(
+                "E0104",
+                "Can't have more than 255 arguments in a call".to_string(),
+                *location,
+            ),
+            Error::MissingDefault { name, location } => Diagnostic::at(
+                "E0105",
+                format!("Parameter `{name}` needs a default value"),
+                *location,
+            )
+            .help("parameters after one with a default must have defaults too".to_string()),
+            Error::ExpectedExpression {
+                token_type,
+                location,
+            } => Diagnostic::at(
+                "E0106",
+                format!("Expected an expression, found {token_type:?}"),
+                *location,
+            ),
+            Error::InvalidAssignment { location } => {
+                Diagnostic::at("E0107", "Invalid assignment target".to_string(), *location)
+                    .label("can't assign to this")
+            }
+            Error::InvalidTokenInUnaryOp {
+                token_type,
+                location,
+            }
+            | Error::InvalidTokenInBinaryOp {
+                token_type,
+                location,
+            } => Diagnostic::at(
+                "E0108",
+                format!("Invalid operator {token_type:?}"),
+                *location,
+            ),
+            Error::ReturnNotInFun { location } => Diagnostic::at(
+                "E0109",
+                "Can't return from top-level code".to_string(),
+                *location,
+            ),
+            Error::BreakNotInLoop { location } => {
+                Diagnostic::at("E0110", "`break` outside of a loop".to_string(), *location)
+            }
+            Error::ContinueNotInLoop { location } => Diagnostic::at(
+                "E0111",
+                "`continue` outside of a loop".to_string(),
+                *location,
+            ),
+            Error::UndefinedLabel { name, location } => Diagnostic::at(
+                "E0112",
+                format!("No enclosing loop labelled `{name}`"),
+                *location,
+            ),
+            Error::DuplicateLabel { name, location } => Diagnostic::at(
+                "E0113",
+                format!("Label `{name}` is already used by an enclosing loop"),
+                *location,
+            )
+            .help("give the inner loop a different label".to_string()),
+            Error::ReadInOwnInitializer { name, location } => Diagnostic::at(
+                "E0201",
+                format!("Can't read local variable `{name}` in its own initializer"),
+                *location,
+            ),
+            Error::AlreadyDeclared { name, location } => Diagnostic::at(
+                "E0202",
+                format!("Already a variable named `{name}` in this scope"),
+                *location,
+            )
+            .help(format!("assign to it with `{name} = ...` instead")),
+            Error::ReturnFromInitializer { location } => Diagnostic::at(
+                "E0203",
+                "Can't return a value from an initializer".to_string(),
+                *location,
+            ),
+            Error::ThisOutsideClass { location } => Diagnostic::at(
+                "E0204",
+                "Can't use `this` outside of a class".to_string(),
+                *location,
+            ),
+            Error::SuperOutsideClass { location } => Diagnostic::at(
+                "E0205",
+                "Can't use `super` outside of a class".to_string(),
+                *location,
+            ),
+            Error::SuperWithoutSuperclass { location } => Diagnostic::at(
+                "E0206",
+                "Can't use `super` in a class with no superclass".to_string(),
+                *location,
+            )
+            .help("declare a superclass with `class Name < Base`".to_string()),
+            Error::InheritFromSelf { location } => Diagnostic::at(
+                "E0207",
+                "A class can't inherit from itself".to_string(),
+                *location,
+            ),
+            Error::RunTimeException { message, location } => {
+                Diagnostic::at("E0301", message.clone(), *location)
+            }
+            Error::UndefinedVariable { name, location } => {
+                Diagnostic::at("E0302", format!("Undefined variable `{name}`"), *location)
+                    .help(format!("give it a value first, e.g. `var {name} = nil;`"))
+            }
+            Error::Throw { value, location } => {
+                Diagnostic::at("E0303", format!("Uncaught exception: {value}"), *location)
+            }
+            Error::JustError { message } => Diagnostic::new("E0300", message.clone(), 0, -1),
+            Error::Traced { error, trace } => {
+                let mut diagnostic = Diagnostic::from(error.as_ref());
+                diagnostic.notes = trace_lines(trace);
+                return diagnostic;
+            }
+            Error::InvalidLoxc { message } => Diagnostic::new(
+                "E0401",
+                format!("Invalid compiled script: {message}"),
+                0,
+                -1,
+            )
+            .help("compile the script again with the `compile` command".to_string()),
+            Error::Exit { code } => {
+                Diagnostic::new("E0304", format!("The script called exit({code})"), 0, -1)
+            }
+        }
+    }
+}
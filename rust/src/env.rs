@@ -1,27 +1,23 @@
-use std::{collections::HashMap, fmt::Display};
+use std::{cell::RefCell, collections::HashMap, fmt::Display, rc::Rc};
 
 use crate::{
     error::Error,
     expr::{Symbol, Value},
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Default)]
 pub struct Environment {
     pub values: HashMap<String, Value>,
-    pub enclosing: Option<Box<Environment>>,
+    pub enclosing: Option<Rc<RefCell<Environment>>>,
 }
 
 impl Display for Environment {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut enclosing = None;
-        if let Some(ref enc) = self.enclosing {
-            enclosing = Some(&enc.values);
+        write!(f, "Environment Values: {0:?}", self.values)?;
+        if let Some(ref enclosing) = self.enclosing {
+            write!(f, ", enclosing: `{0}`", enclosing.borrow())?;
         }
-        return write!(
-            f,
-            "Environment Values: {0:?}, enclosing: `{enclosing:?}`",
-            self.values
-        );
+        return Ok(());
     }
 }
 
@@ -31,51 +27,74 @@ impl Environment {
     }
 
     pub fn get(&self, name: &Symbol) -> Result<Value, Error> {
-        if !self.values.contains_key(&name.name) {
-            if let Some(ref enclosing) = self.enclosing {
-                return enclosing.get(name);
-            }
-            let err = format!("Undefined variable {0}.", name.name);
-            return Err(Error::RunTimeException {
-                message: err,
-                line: name.line,
-                col: name.col,
-            });
-        }
-        let val = self.values.get(&name.name);
-        match val {
+        match self.values.get(&name.name) {
             Some(val) => Ok(val.clone()),
             None => {
-                let err = format!("Undefined variable {0}.", name.name);
-                return Err(Error::RunTimeException {
-                    message: err,
-                    line: name.line,
-                    col: name.col,
+                if let Some(ref enclosing) = self.enclosing {
+                    return enclosing.borrow().get(name);
+                }
+                return Err(Error::UndefinedVariable {
+                    name: name.name.clone(),
+                    location: name.location(),
                 });
             }
         }
     }
 
     pub fn assign(&mut self, symbol: &Symbol, value: Value) -> Result<(), Error> {
-        if self.values.contains_key(&symbol.name) {
-            *self.values.get_mut(&symbol.name).unwrap() = value;
+        if let Some(slot) = self.values.get_mut(&symbol.name) {
+            *slot = value;
             return Ok(());
         }
-        if let Some(ref mut enclosing) = self.enclosing {
-            return enclosing.assign(symbol, value);
+        if let Some(ref enclosing) = self.enclosing {
+            return enclosing.borrow_mut().assign(symbol, value);
+        }
+        return Err(Error::UndefinedVariable {
+            name: symbol.name.clone(),
+            location: symbol.location(),
+        });
+    }
+
+    pub fn get_at(&self, distance: usize, name: &Symbol) -> Result<Value, Error> {
+        if distance > 0 {
+            if let Some(ref enclosing) = self.enclosing {
+                return enclosing.borrow().get_at(distance - 1, name);
+            }
+        } else if let Some(val) = self.values.get(&name.name) {
+            return Ok(val.clone());
+        }
+        return Err(Error::UndefinedVariable {
+            name: name.name.clone(),
+            location: name.location(),
+        });
+    }
+
+    pub fn assign_at(
+        &mut self,
+        distance: usize,
+        symbol: &Symbol,
+        value: Value,
+    ) -> Result<(), Error> {
+        if distance > 0 {
+            if let Some(ref enclosing) = self.enclosing {
+                return enclosing
+                    .borrow_mut()
+                    .assign_at(distance - 1, symbol, value);
+            }
+        } else if let Some(slot) = self.values.get_mut(&symbol.name) {
+            *slot = value;
+            return Ok(());
         }
-        let msg = format!("Undefined Variable `{0}`.", symbol.name);
-        return Err(Error::RunTimeException {
-            message: msg,
-            line: symbol.line,
-            col: symbol.col,
+        return Err(Error::UndefinedVariable {
+            name: symbol.name.clone(),
+            location: symbol.location(),
         });
     }
 
-    pub fn with_enclosing(environment: Environment) -> Environment {
+    pub fn with_enclosing(environment: Rc<RefCell<Environment>>) -> Environment {
         return Self {
             values: HashMap::new(),
-            enclosing: Some(Box::new(environment)),
+            enclosing: Some(environment),
         };
     }
 }
@@ -1,7 +1,7 @@
 use std::fmt;
 
 use crate::{
-    expr::{Expr, Value},
+    expr::{SourceLocation, Value},
     scanner,
 };
 
@@ -25,63 +25,107 @@ use crate::{
 //     return ();
 // }
 pub enum Error {
-    UnexpectedToken(scanner::Token),
+    UnexpectedToken(Box<scanner::Token>),
     TokenMismatch {
         expected: scanner::TokenType,
-        found: scanner::Token,
+        found: Box<scanner::Token>,
         maybe_on_err_string: Option<String>,
     },
     MaxParamsExceeded {
         kind: FunctionKind,
-        line: usize,
-        col: i64,
+        location: SourceLocation,
     },
     ReturnNotInFun {
-        line: usize,
-        col: i64,
+        location: SourceLocation,
     },
     InvalidAssignment {
-        line: usize,
-        col: i64,
+        location: SourceLocation,
     },
     TooManyArguments {
-        line: usize,
-        col: i64,
+        location: SourceLocation,
+    },
+    MissingDefault {
+        name: String,
+        location: SourceLocation,
     },
     ExpectedExpression {
         token_type: scanner::TokenType,
-        line: usize,
-        col: i64,
+        location: SourceLocation,
     },
     InvalidTokenInUnaryOp {
         token_type: scanner::TokenType,
-        line: usize,
-        col: i64,
+        location: SourceLocation,
     },
     InvalidTokenInBinaryOp {
         token_type: scanner::TokenType,
-        line: usize,
-        col: i64,
+        location: SourceLocation,
     },
     RunTimeException {
         message: String,
-        line: usize,
-        col: i64,
+        location: SourceLocation,
     },
     JustError {
         message: String,
     },
     BreakNotInLoop {
-        line: usize,
-        col: i64,
+        location: SourceLocation,
+    },
+    ContinueNotInLoop {
+        location: SourceLocation,
+    },
+    UndefinedLabel {
+        name: String,
+        location: SourceLocation,
     },
-    Return {
+    DuplicateLabel {
+        name: String,
+        location: SourceLocation,
+    },
+    /// Raised by the `exit` native to stop the script, with `code` as the
+    /// process exit status.
+    Exit {
+        code: u8,
+    },
+    Throw {
         value: Value,
+        location: SourceLocation,
+    },
+    /// An uncaught exception together with the calls that were active when
+    /// it was raised, innermost first.
+    Traced {
+        error: Box<Error>,
+        trace: Vec<StackFrame>,
     },
     UndefinedVariable {
         name: String,
-        line: usize,
-        col: i64,
+        location: SourceLocation,
+    },
+    ReadInOwnInitializer {
+        name: String,
+        location: SourceLocation,
+    },
+    AlreadyDeclared {
+        name: String,
+        location: SourceLocation,
+    },
+    ReturnFromInitializer {
+        location: SourceLocation,
+    },
+    ThisOutsideClass {
+        location: SourceLocation,
+    },
+    SuperOutsideClass {
+        location: SourceLocation,
+    },
+    SuperWithoutSuperclass {
+        location: SourceLocation,
+    },
+    InheritFromSelf {
+        location: SourceLocation,
+    },
+    /// A `.loxc` file that could not be loaded.
+    InvalidLoxc {
+        message: String,
     },
 }
 
@@ -108,71 +152,205 @@ impl fmt::Debug for Error {
                 }
                 fmt::Result::Ok(())
             }
-            Error::MaxParamsExceeded { kind, line, col } => write!(
+            Error::MaxParamsExceeded { kind, location: SourceLocation { line, col, .. } } => write!(
                 f,
                 "Cannot have more than 255 parameters in a {:?} declaration. Line={},col={}",
                 kind, line, col
             ),
-            Error::ReturnNotInFun { line, col } => write!(
+            Error::ReturnNotInFun { location: SourceLocation { line, col, .. } } => write!(
                 f,
                 "return statement not enclosed in a FunDecl at line={},col={}",
                 line, col
             ),
-            Error::InvalidAssignment { line, col } => {
+            Error::InvalidAssignment { location: SourceLocation { line, col, .. } } => {
                 write!(f, "invalid assignment target at line={},col={}", line, col)
             }
-            Error::TooManyArguments { line, col } => write!(
+            Error::TooManyArguments { location: SourceLocation { line, col, .. } } => write!(
                 f,
                 "Cannot have more than 255 arguments to a function call. Line={},col={}",
                 line, col
             ),
+            Error::MissingDefault { name, location: SourceLocation { line, col, .. } } => write!(
+                f,
+                "Parameter `{name}` needs a default value because an earlier parameter has one at line={line},col={col}"
+            ),
             Error::ExpectedExpression {
                 token_type,
-                line,
-                col,
-            } => write!(
+                location: SourceLocation { line, col, .. } } => write!(
                 f,
                 "Expected expression, but found token {:?} at line={},col={}",
                 token_type, line, col
             ),
             Error::InvalidTokenInUnaryOp {
                 token_type,
-                line,
-                col,
-            } => write!(
+                location: SourceLocation { line, col, .. } } => write!(
                 f,
                 "invalid token in unary op {:?} at line={},col={}",
                 token_type, line, col
             ),
             Error::InvalidTokenInBinaryOp {
                 token_type,
-                line,
-                col,
-            } => write!(
+                location: SourceLocation { line, col, .. } } => write!(
                 f,
                 "invalid token in binary op {:?} at line={},col={}",
                 token_type, line, col
             ),
-            Error::RunTimeException { message, line, col } => write!(
+            Error::RunTimeException { message, location: SourceLocation { line, col, .. } } => write!(
                 f,
                 "Invalid exceptions {message:?} at line={},col={}",
                 line, col
             ),
             Error::JustError { message } => write!(f, "Something Went wrong!. {message}"),
-            Error::BreakNotInLoop { line, col } => {
+            Error::BreakNotInLoop { location: SourceLocation { line, col, .. } } => {
                 write!(f, "Break not in loop at line={line},col={col}")
             }
-            Error::Return { value } => write!(f, "Return: {value}"),
-            Error::UndefinedVariable { name, line, col } => {
+            Error::ContinueNotInLoop { location: SourceLocation { line, col, .. } } => {
+                write!(f, "Continue not in loop at line={line},col={col}")
+            }
+            Error::UndefinedLabel { name, location: SourceLocation { line, col, .. } } => {
+                write!(
+                    f,
+                    "No enclosing loop labelled `{name}` at line={line},col={col}"
+                )
+            }
+            Error::DuplicateLabel { name, location: SourceLocation { line, col, .. } } => write!(
+                f,
+                "Label `{name}` is already used by an enclosing loop at line={line},col={col}"
+            ),
+            Error::Exit { code } => write!(f, "Exit: {code}"),
+            Error::Throw { value, location: SourceLocation { line, col, .. } } => {
+                write!(f, "Uncaught exception {value} at line={line},col={col}")
+            }
+            Error::Traced { error, trace } => {
+                write!(f, "{error:?}")?;
+                for line in trace_lines(trace) {
+                    write!(f, "\n    {line}")?;
+                }
+                fmt::Result::Ok(())
+            }
+            Error::UndefinedVariable { name, location: SourceLocation { line, col, .. } } => {
                 write!(f, "Undefined variable `{name}` at line={line},col={col}")
             }
+            Error::ReadInOwnInitializer { name, location: SourceLocation { line, col, .. } } => write!(
+                f,
+                "Can't read local variable `{name}` in its own initializer at line={line},col={col}"
+            ),
+            Error::AlreadyDeclared { name, location: SourceLocation { line, col, .. } } => write!(
+                f,
+                "Already a variable named `{name}` in this scope at line={line},col={col}"
+            ),
+            Error::ReturnFromInitializer { location: SourceLocation { line, col, .. } } => write!(
+                f,
+                "Can't return a value from an initializer at line={line},col={col}"
+            ),
+            Error::ThisOutsideClass { location: SourceLocation { line, col, .. } } => {
+                write!(
+                    f,
+                    "Can't use `this` outside of a class at line={line},col={col}"
+                )
+            }
+            Error::SuperOutsideClass { location: SourceLocation { line, col, .. } } => {
+                write!(
+                    f,
+                    "Can't use `super` outside of a class at line={line},col={col}"
+                )
+            }
+            Error::SuperWithoutSuperclass { location: SourceLocation { line, col, .. } } => write!(
+                f,
+                "Can't use `super` in a class with no superclass at line={line},col={col}"
+            ),
+            Error::InheritFromSelf { location: SourceLocation { line, col, .. } } => {
+                write!(
+                    f,
+                    "A class can't inherit from itself at line={line},col={col}"
+                )
+            }
+            Error::InvalidLoxc { message } => {
+                write!(f, "Invalid compiled script: {message}")
+            }
+        }
+    }
+}
+
+impl Error {
+    /// Whether this error is an exception that `catch` can handle, as opposed
+    /// to a control-flow signal or a static error.
+    pub fn is_exception(&self) -> bool {
+        return matches!(
+            self,
+            Error::Throw { .. }
+                | Error::RunTimeException { .. }
+                | Error::UndefinedVariable { .. }
+                | Error::JustError { .. }
+                | Error::Traced { .. }
+        );
+    }
+
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Error::Throw { location, .. }
+            | Error::RunTimeException { location, .. }
+            | Error::UndefinedVariable { location, .. } => Some(location.line),
+            Error::Traced { error, .. } => error.line(),
+            _ => None,
+        }
+    }
+}
+
+/// One entry of a traceback: the function that was running and the line it
+/// was executing.
+#[derive(Debug, Clone)]
+pub struct StackFrame {
+    pub function: String,
+    pub line: usize,
+}
+
+impl fmt::Display for StackFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "at {} (line {})", self.function, self.line)
+    }
+}
+
+/// Runs of identical frames longer than this are cut short, so deep
+/// recursion doesn't bury the rest of the traceback.
+const REPEATED_FRAMES_SHOWN: usize = 3;
+
+/// The lines of a traceback, innermost call first. Only the first few frames
+/// of a run of identical ones are shown, followed by a count of the rest.
+pub fn trace_lines(trace: &[StackFrame]) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut i = 0;
+    while i < trace.len() {
+        let frame = &trace[i];
+        let run = trace[i..]
+            .iter()
+            .take_while(|other| other.function == frame.function && other.line == frame.line)
+            .count();
+        let shown = run.min(REPEATED_FRAMES_SHOWN);
+        lines.extend(std::iter::repeat_n(frame.to_string(), shown));
+        match run - shown {
+            0 => (),
+            1 => lines.push("... 1 more frame".to_string()),
+            hidden => lines.push(format!("... {hidden} more frames")),
         }
+        i += run;
     }
+    return lines;
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum FunctionKind {
     Function,
     Method,
     Lambda,
 }
+
+impl fmt::Display for FunctionKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FunctionKind::Function => write!(f, "function"),
+            FunctionKind::Method => write!(f, "method"),
+            FunctionKind::Lambda => write!(f, "lambda"),
+        }
+    }
+}
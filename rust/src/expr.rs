@@ -1,103 +1,340 @@
 use core::{fmt, panic};
-use std::collections::HashMap;
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
 
 use crate::{
     env::Environment,
-    interpreter::{self, Callable, Interpreter},
+    error::Error,
+    interpreter::{Callable, Interpreter, Unwind},
+    natives::NativeContext,
     scanner::Token,
+    vm::Closure,
 };
 
 #[derive(Debug, Clone)]
-pub enum Expr {
+pub struct Expr {
+    pub kind: ExprKind,
+    /// Where the expression starts, with a span covering all of it.
+    pub location: SourceLocation,
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, location: SourceLocation) -> Self {
+        return Self { kind, location };
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ExprKind {
     Literal(Literal),
-    // This(SourceLocation),
+    This(SourceLocation),
     Unary(UnaryOp, Box<Expr>),
     Binary(Box<Expr>, BinaryOp, Box<Expr>),
     Call(Box<Expr>, SourceLocation, Vec<Expr>),
-    // Get(Box<Expr>, Symbol),
+    Get(Box<Expr>, Symbol),
     Grouping(Box<Expr>),
     Variable(Symbol),
     Assign(Symbol, Box<Expr>),
     Logical(Box<Expr>, LogicalOp, Box<Expr>),
-    // Set(Box<Expr>, Symbol, Box<Expr>),
-    // Super(SourceLocation, Symbol),
-    // List(Vec<Expr>),
-    // Subscript {
-    //     value: Box<Expr>,
-    //     slice: Box<Expr>,
-    //     source_location: SourceLocation,
-    // },
-    // SetItem {
-    //     lhs: Box<Expr>,
-    //     slice: Box<Expr>,
-    //     rhs: Box<Expr>,
-    //     source_location: SourceLocation,
-    // },
-    // Lambda(LambdaDecl),
+    Set(Box<Expr>, Symbol, Box<Expr>),
+    Super(SourceLocation, Symbol),
+    List(Vec<Expr>),
+    Subscript {
+        value: Box<Expr>,
+        slice: Box<Expr>,
+        source_location: SourceLocation,
+    },
+    SetItem {
+        lhs: Box<Expr>,
+        slice: Box<Expr>,
+        rhs: Box<Expr>,
+        source_location: SourceLocation,
+    },
+    Map(Vec<(Expr, Expr)>, SourceLocation),
+    Lambda(Box<LambdaDecl>),
+    /// `...list` in the arguments of a call.
+    Spread(Box<Expr>),
 }
 
-#[derive(Debug, Clone)]
+/// The Rust side of a native function. An `Err` becomes a runtime error at
+/// the call site.
+pub type NativeCallable = Rc<dyn Fn(&mut dyn NativeContext, &[Value]) -> Result<Value, String>>;
+
+#[derive(Clone)]
 pub struct NativeFunction {
     pub name: String,
+    /// Number of arguments the function takes, or the minimum number when it
+    /// is variadic.
     pub arity: u8,
-    pub callable: fn(&mut Interpreter, &[Value]) -> Result<Value, String>,
+    pub variadic: bool,
+    pub callable: NativeCallable,
 }
 
-#[derive(Debug, Clone)]
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NativeFunction({})", self.name)
+    }
+}
+
+impl NativeFunction {
+    pub fn new(
+        name: &str,
+        arity: u8,
+        callable: impl Fn(&mut dyn NativeContext, &[Value]) -> Result<Value, String> + 'static,
+    ) -> Self {
+        return Self {
+            name: name.to_string(),
+            arity,
+            variadic: false,
+            callable: Rc::new(callable),
+        };
+    }
+
+    /// A native that accepts `min_arity` or more arguments.
+    pub fn variadic(
+        name: &str,
+        min_arity: u8,
+        callable: impl Fn(&mut dyn NativeContext, &[Value]) -> Result<Value, String> + 'static,
+    ) -> Self {
+        return Self {
+            variadic: true,
+            ..Self::new(name, min_arity, callable)
+        };
+    }
+}
+
+/// How many arguments a callable accepts: at least `min`, and at most `max`
+/// unless it has no upper bound.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Arity {
+    pub min: usize,
+    pub max: Option<usize>,
+}
+
+impl Arity {
+    pub fn exact(count: usize) -> Self {
+        return Self {
+            min: count,
+            max: Some(count),
+        };
+    }
+
+    pub fn accepts(&self, count: usize) -> bool {
+        return count >= self.min && self.max.is_none_or(|max| count <= max);
+    }
+}
+
+impl From<&FunDecl> for Arity {
+    fn from(fun_decl: &FunDecl) -> Self {
+        let required = fun_decl
+            .params
+            .iter()
+            .filter(|param| param.default.is_none())
+            .count();
+        return Self {
+            min: required,
+            max: match fun_decl.rest {
+                Some(_) => None,
+                None => Some(fun_decl.params.len()),
+            },
+        };
+    }
+}
+
+impl From<&NativeFunction> for Arity {
+    fn from(native: &NativeFunction) -> Self {
+        let arity = native.arity as usize;
+        return Self {
+            min: arity,
+            max: if native.variadic { None } else { Some(arity) },
+        };
+    }
+}
+
+#[derive(Clone)]
 pub struct LoxFunction {
-    pub declaration: Stmt,
+    /// Shared by every copy of the function, so cloning it is cheap.
+    pub declaration: Rc<FunDecl>,
+    pub closure: Rc<RefCell<Environment>>,
+    pub is_initializer: bool,
+}
+
+impl fmt::Debug for LoxFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "LoxFunction({})", self.declaration.name.name)
+    }
+}
+
+impl LoxFunction {
+    pub fn name(&self) -> &str {
+        return &self.declaration.name.name;
+    }
+
+    pub fn bind(&self, instance: Rc<RefCell<LoxInstance>>) -> LoxFunction {
+        let mut env = Environment::with_enclosing(self.closure.clone());
+        env.values
+            .insert("this".to_string(), Value::Instance(instance));
+        return LoxFunction {
+            declaration: self.declaration.clone(),
+            closure: Rc::new(RefCell::new(env)),
+            is_initializer: self.is_initializer,
+        };
+    }
 }
 
 impl Callable for LoxFunction {
-    fn call(&self, interpreter: &mut Interpreter, arguments: &[Value]) -> Result<Value, String> {
-        match &self.declaration {
-            Stmt::FunDecl(fun_decl) => {
-                let args_env: HashMap<_, _> = fun_decl
-                    .params
-                    .iter()
-                    .zip(arguments.iter())
-                    .map(|(param, arg)| (param.name.clone(), arg.clone()))
-                    .collect();
-
-                let saved_env = interpreter.env.clone();
-                let saved_retval = interpreter.ret_val.clone();
-                let mut env = Environment::with_enclosing(saved_env.clone());
-                env.values.extend(args_env);
-
-                let res = interpreter.interpret_block(&fun_decl.body, env);
-
-                match res {
-                    Ok(_) => {
-                        interpreter.env = saved_env;
-                        interpreter.ret_val = saved_retval.clone();
-                        return Ok(Value::Nil);
-                    }
-                    Err(v) => match v {
-                        crate::error::Error::Return { value } => {
-                            interpreter.env = saved_env;
-                            interpreter.ret_val = saved_retval.clone();
-                            return Ok(value);
-                        }
-                        _ => {
-                            return Err("ent Wrong!".to_string());
-                        }
-                    },
-                }
+    fn call(&self, interpreter: &mut Interpreter, arguments: &[Value]) -> Result<Value, Error> {
+        let fun_decl = self.declaration.as_ref();
+        let env = Environment::with_enclosing(self.closure.clone());
+        let env = Rc::new(RefCell::new(env));
+        // Defaults run inside the new environment so they can see the
+        // parameters before them.
+        let previous = std::mem::replace(&mut interpreter.env, env.clone());
+        let bound = bind_arguments(interpreter, fun_decl, arguments);
+        interpreter.env = previous;
+        bound?;
+
+        let res = interpreter.interpret_block(&fun_decl.body, env);
+
+        let value = match res {
+            Ok(_) => Value::Nil,
+            Err(Unwind::Return(value)) => value,
+            Err(unwind) => return Err(unwind.into_error()),
+        };
+        if self.is_initializer {
+            if let Some(this) = self.closure.borrow().values.get("this") {
+                return Ok(this.clone());
             }
-            _ => Err("Not a func".to_string()),
         }
+        return Ok(value);
     }
 
-    fn arity(&self, _: &Interpreter) -> u8 {
-        match &self.declaration {
-            Stmt::FunDecl(fun_decl) => {
-                return fun_decl.params.len() as u8;
-            }
-            _ => return 0,
+    fn arity(&self, _: &Interpreter) -> Arity {
+        return Arity::from(self.declaration.as_ref());
+    }
+}
+
+/// Defines the parameters of `fun_decl` in the interpreter's current
+/// environment. Arity has already been checked, so every parameter without a
+/// default has an argument.
+fn bind_arguments(
+    interpreter: &mut Interpreter,
+    fun_decl: &FunDecl,
+    arguments: &[Value],
+) -> Result<(), Error> {
+    for (i, param) in fun_decl.params.iter().enumerate() {
+        let value = match (arguments.get(i), &param.default) {
+            (Some(argument), _) => argument.clone(),
+            (None, Some(default)) => interpreter.get_value(default)?,
+            (None, None) => Value::Undefined,
+        };
+        interpreter
+            .env
+            .borrow_mut()
+            .values
+            .insert(param.name.name.clone(), value);
+    }
+    if let Some(rest) = &fun_decl.rest {
+        let extra = arguments.get(fun_decl.params.len()..).unwrap_or(&[]);
+        interpreter.env.borrow_mut().values.insert(
+            rest.name.clone(),
+            Value::List(Rc::new(RefCell::new(extra.to_vec()))),
+        );
+    }
+    return Ok(());
+}
+
+pub struct LoxClass {
+    pub name: String,
+    pub superclass: Option<Rc<LoxClass>>,
+    /// Methods are `LoxFunction`s when the class was declared by the tree
+    /// walker and `Closure`s when it was declared by the bytecode VM.
+    pub methods: HashMap<String, Value>,
+}
+
+impl fmt::Debug for LoxClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "LoxClass({})", self.name)
+    }
+}
+
+impl LoxClass {
+    pub fn find_method(&self, name: &str) -> Option<Value> {
+        if let Some(method) = self.methods.get(name) {
+            return Some(method.clone());
+        }
+        if let Some(superclass) = &self.superclass {
+            return superclass.find_method(name);
+        }
+        return None;
+    }
+}
+
+impl Callable for Rc<LoxClass> {
+    fn call(&self, interpreter: &mut Interpreter, arguments: &[Value]) -> Result<Value, Error> {
+        let instance = Rc::new(RefCell::new(LoxInstance {
+            class: self.clone(),
+            fields: HashMap::new(),
+        }));
+        if let Some(Value::LoxFunction(initializer)) = self.find_method("init") {
+            initializer
+                .bind(instance.clone())
+                .call(interpreter, arguments)?;
+        }
+        return Ok(Value::Instance(instance));
+    }
+
+    fn arity(&self, interpreter: &Interpreter) -> Arity {
+        match self.find_method("init") {
+            Some(Value::LoxFunction(initializer)) => return initializer.arity(interpreter),
+            _ => return Arity::exact(0),
         }
     }
 }
 
+pub struct LoxInstance {
+    pub class: Rc<LoxClass>,
+    pub fields: HashMap<String, Value>,
+}
+
+impl fmt::Debug for LoxInstance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "LoxInstance({})", self.class.name)
+    }
+}
+
+impl LoxInstance {
+    pub fn get(instance: &Rc<RefCell<LoxInstance>>, name: &Symbol) -> Result<Value, Error> {
+        if let Some(value) = instance.borrow().fields.get(&name.name) {
+            return Ok(value.clone());
+        }
+        let class = instance.borrow().class.clone();
+        if let Some(method) = class.find_method(&name.name) {
+            return Ok(bind_method(method, instance.clone()));
+        }
+        return Err(Error::RunTimeException {
+            message: format!("Undefined property `{0}`.", name.name),
+            location: name.location(),
+        });
+    }
+
+    pub fn set(&mut self, name: &Symbol, value: Value) {
+        self.fields.insert(name.name.clone(), value);
+    }
+}
+
+/// Binds `this` to `instance` in a method found on its class.
+pub fn bind_method(method: Value, instance: Rc<RefCell<LoxInstance>>) -> Value {
+    match method {
+        Value::LoxFunction(function) => Value::LoxFunction(function.bind(instance)),
+        Value::Closure(closure) => Value::Closure(Rc::new(closure.bind(Value::Instance(instance)))),
+        other => other,
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Value {
     String(String),
@@ -105,45 +342,230 @@ pub enum Value {
     Bool(bool),
     NativeFunction(NativeFunction),
     LoxFunction(LoxFunction),
+    Closure(Rc<Closure>),
+    Class(Rc<LoxClass>),
+    Instance(Rc<RefCell<LoxInstance>>),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<LoxMap>>),
     Nil,
-    Undefined
+    Undefined,
 }
 
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return self.write(f, &mut HashSet::new());
+    }
+}
+
+impl Value {
+    /// Writes the value as `print` shows it. `printing` holds the lists and
+    /// maps being written further up, so one that contains itself prints as
+    /// `[...]` or `{...}` there instead of recursing forever.
+    fn write(&self, f: &mut fmt::Formatter<'_>, printing: &mut HashSet<*const ()>) -> fmt::Result {
         match self {
             Value::String(s) => write!(f, "{}", s),
+            // Whole numbers print without a fractional part, e.g. `3`.
             Value::Number(v) => write!(f, "{}", v),
             Value::Bool(b) => write!(f, "{}", b),
-            Value::Nil => write!(f, "Nil"),
+            Value::Nil => write!(f, "nil"),
             Value::Undefined => write!(f, "Undefined"),
-            Value::NativeFunction(_) => write!(f, "<Native Fn>"),
-            Value::LoxFunction(v) => match &v.declaration {
-                Stmt::FunDecl(v) => write!(f, "<fn {} >", v.name.name),
-                _ => write!(f, "Not a function"),
-            },
+            Value::NativeFunction(_) => write!(f, "<native fn>"),
+            Value::LoxFunction(v) => write!(f, "<fn {}>", v.declaration.name.name),
+            Value::Closure(closure) => write!(f, "<fn {}>", closure.function.name),
+            Value::Class(class) => write!(f, "{}", class.name),
+            Value::Instance(instance) => write!(f, "{} instance", instance.borrow().class.name),
+            Value::List(list) => {
+                let ptr = Rc::as_ptr(list) as *const ();
+                if !printing.insert(ptr) {
+                    return write!(f, "[...]");
+                }
+                write!(f, "[")?;
+                for (i, item) in list.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    item.write(f, printing)?;
+                }
+                printing.remove(&ptr);
+                write!(f, "]")
+            }
+            Value::Map(map) => {
+                let ptr = Rc::as_ptr(map) as *const ();
+                if !printing.insert(ptr) {
+                    return write!(f, "{{...}}");
+                }
+                write!(f, "{{")?;
+                for (i, (key, value)) in map.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: ", Value::from(key.clone()))?;
+                    value.write(f, printing)?;
+                }
+                printing.remove(&ptr);
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// The subset of values that can be used as map keys. Strings, booleans and
+/// `nil` hash by value; numbers hash by their bit pattern with `-0` folded into
+/// `0`, and `NaN` is rejected since it is never equal to itself. Everything
+/// else (functions, classes, instances, lists, maps) has identity or mutable
+/// state and cannot be a key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    String(String),
+    Number(u64),
+    Bool(bool),
+    Nil,
+}
+
+impl TryFrom<&Value> for MapKey {
+    type Error = String;
+
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::String(s) => Ok(MapKey::String(s.clone())),
+            Value::Number(n) if n.is_nan() => Err("NaN can't be used as a map key.".to_string()),
+            Value::Number(n) if *n == 0.0 => Ok(MapKey::Number(0f64.to_bits())),
+            Value::Number(n) => Ok(MapKey::Number(n.to_bits())),
+            Value::Bool(b) => Ok(MapKey::Bool(*b)),
+            Value::Nil => Ok(MapKey::Nil),
+            other => Err(format!("{other} can't be used as a map key.")),
         }
     }
 }
 
+impl From<MapKey> for Value {
+    fn from(key: MapKey) -> Self {
+        match key {
+            MapKey::String(s) => Value::String(s),
+            MapKey::Number(bits) => Value::Number(f64::from_bits(bits)),
+            MapKey::Bool(b) => Value::Bool(b),
+            MapKey::Nil => Value::Nil,
+        }
+    }
+}
+
+/// A map that remembers insertion order, so printing and `keys`/`values` are
+/// deterministic.
+#[derive(Debug, Default)]
+pub struct LoxMap {
+    entries: Vec<(MapKey, Value)>,
+    index: HashMap<MapKey, usize>,
+}
+
+impl LoxMap {
+    pub fn get(&self, key: &MapKey) -> Option<&Value> {
+        return self.index.get(key).map(|i| &self.entries[*i].1);
+    }
+
+    pub fn insert(&mut self, key: MapKey, value: Value) {
+        match self.index.get(&key) {
+            Some(i) => self.entries[*i].1 = value,
+            None => {
+                self.index.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &MapKey) -> Option<Value> {
+        let i = self.index.remove(key)?;
+        let (_, value) = self.entries.remove(i);
+        for position in self.index.values_mut() {
+            if *position > i {
+                *position -= 1;
+            }
+        }
+        return Some(value);
+    }
+
+    pub fn contains_key(&self, key: &MapKey) -> bool {
+        return self.index.contains_key(key);
+    }
+
+    pub fn len(&self) -> usize {
+        return self.entries.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.entries.is_empty();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(MapKey, Value)> {
+        return self.entries.iter();
+    }
+}
+
 impl Callable for NativeFunction {
-    fn call(
-        &self,
-        interpreter: &mut interpreter::Interpreter,
-        arguments: &[Value],
-    ) -> Result<Value, String> {
-        return (self.callable)(interpreter, arguments);
+    fn call(&self, interpreter: &mut Interpreter, arguments: &[Value]) -> Result<Value, Error> {
+        let result =
+            (self.callable)(interpreter, arguments).map_err(|message| Error::JustError { message });
+        if let Some(code) = interpreter.exit_code.take() {
+            return Err(Error::Exit { code });
+        }
+        return result;
     }
 
-    fn arity(&self, _: &Interpreter) -> u8 {
-        return self.arity;
+    fn arity(&self, _: &Interpreter) -> Arity {
+        return Arity::from(self);
+    }
+}
+
+/// Identifies the source text a span points into: a script file, one REPL
+/// input or one string passed to `eval`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct FileId(pub u32);
+
+/// Bytes `start..end` of the source identified by `file`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub file: FileId,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// The span from the start of `self` to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        return Span {
+            end: other.end,
+            ..self
+        };
     }
 }
 
+/// A line and 1-based column, plus the span of the token or node found there.
+/// Locations made up by the interpreter rather than read from source have a
+/// column of -1.
 #[derive(Debug, Clone, Copy)]
 pub struct SourceLocation {
     pub line: usize,
     pub col: i64,
+    pub span: Span,
+}
+
+impl SourceLocation {
+    /// A location for code that has no source, such as a call made by the
+    /// host.
+    pub fn none() -> Self {
+        return Self {
+            line: 0,
+            col: -1,
+            span: Span::default(),
+        };
+    }
+
+    /// This location, with its span extended to the end of `end`.
+    pub fn to(self, end: Span) -> Self {
+        return Self {
+            span: self.span.to(end),
+            ..self
+        };
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -152,23 +574,58 @@ pub enum LogicalOp {
     And,
 }
 
+/// A name as written in the source. Two symbols are only equal when they
+/// are the same occurrence, so they can key per-use data such as the
+/// resolver's scope distances.
 #[derive(Debug, Eq, PartialEq, Hash, Clone)]
 pub struct Symbol {
     pub name: String,
     pub line: usize,
     pub col: i64,
+    pub span: Span,
+}
+
+impl Symbol {
+    /// A symbol for a name the interpreter defines itself, such as a native.
+    pub fn global(name: &str) -> Self {
+        return Symbol {
+            name: name.to_string(),
+            line: 0,
+            col: -1,
+            span: Span::default(),
+        };
+    }
+
+    pub fn location(&self) -> SourceLocation {
+        return SourceLocation {
+            line: self.line,
+            col: self.col,
+            span: self.span,
+        };
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Param {
+    pub name: Symbol,
+    /// Evaluated when the call leaves the parameter out.
+    pub default: Option<Expr>,
 }
 
 #[derive(Debug, Clone)]
 pub struct FunDecl {
     pub name: Symbol,
-    pub params: Vec<Symbol>,
+    pub params: Vec<Param>,
+    /// A `...rest` parameter that collects extra arguments into a list.
+    pub rest: Option<Symbol>,
     pub body: Vec<Stmt>,
 }
 
 #[derive(Debug, Clone)]
 pub struct LambdaDecl {
-    pub params: Vec<Symbol>,
+    pub keyword: SourceLocation,
+    pub params: Vec<Param>,
+    pub rest: Option<Symbol>,
     pub body: Vec<Stmt>,
 }
 
@@ -180,7 +637,20 @@ pub struct ClassDecl {
 }
 
 #[derive(Debug, Clone)]
-pub enum Stmt {
+pub struct Stmt {
+    pub kind: StmtKind,
+    /// Where the statement starts, with a span covering all of it.
+    pub location: SourceLocation,
+}
+
+impl Stmt {
+    pub fn new(kind: StmtKind, location: SourceLocation) -> Self {
+        return Self { kind, location };
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum StmtKind {
     Expr(Expr),
     FunDecl(FunDecl),
     ClassDecl(ClassDecl),
@@ -189,8 +659,20 @@ pub enum Stmt {
     VarDecl(Symbol, Option<Expr>),
     Block(Vec<Stmt>),
     Return(SourceLocation, Option<Expr>),
-    While(Expr, Box<Stmt>),
-    Break,
+    While {
+        condition: Expr,
+        body: Box<Stmt>,
+        increment: Option<Expr>,
+        label: Option<Symbol>,
+    },
+    Break(SourceLocation, Option<Symbol>),
+    Continue(SourceLocation, Option<Symbol>),
+    Throw(SourceLocation, Expr),
+    Try {
+        body: Vec<Stmt>,
+        catch: Option<(Symbol, Vec<Stmt>)>,
+        finally: Option<Vec<Stmt>>,
+    },
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -204,20 +686,31 @@ pub struct UnaryOp {
     pub ty: UnaryOpTy,
     pub line: usize,
     pub col: i64,
+    pub span: Span,
 }
 
 impl UnaryOp {
+    pub fn location(&self) -> SourceLocation {
+        return SourceLocation {
+            line: self.line,
+            col: self.col,
+            span: self.span,
+        };
+    }
+
     pub(crate) fn from_token(current: &Token) -> UnaryOp {
         return match current.ty {
             crate::scanner::TokenType::Minus => Self {
                 ty: UnaryOpTy::Minus,
                 line: current.line,
                 col: current.col,
+                span: current.span,
             },
             crate::scanner::TokenType::Bang => Self {
                 ty: UnaryOpTy::Bang,
                 line: current.line,
                 col: current.col,
+                span: current.span,
             },
             _ => panic!(
                 "this was not supposed to happen! This token `{current:?}` is not a `BinaryOpTy`"
@@ -240,65 +733,102 @@ pub enum BinaryOpTy {
     Slash,
 }
 
+impl fmt::Display for BinaryOpTy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            BinaryOpTy::EqualEqual => "==",
+            BinaryOpTy::NotEqual => "!=",
+            BinaryOpTy::Less => "<",
+            BinaryOpTy::LessEqual => "<=",
+            BinaryOpTy::Greater => ">",
+            BinaryOpTy::GreaterEqual => ">=",
+            BinaryOpTy::Plus => "+",
+            BinaryOpTy::Minus => "-",
+            BinaryOpTy::Star => "*",
+            BinaryOpTy::Slash => "/",
+        };
+        return write!(f, "{symbol}");
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct BinaryOp {
     pub ty: BinaryOpTy,
     pub line: usize,
     pub col: i64,
+    pub span: Span,
 }
 
 impl BinaryOp {
+    pub fn location(&self) -> SourceLocation {
+        return SourceLocation {
+            line: self.line,
+            col: self.col,
+            span: self.span,
+        };
+    }
+
     pub fn from_token(value: &Token) -> Self {
         match value.ty {
             crate::scanner::TokenType::Minus => Self {
                 ty: BinaryOpTy::Minus,
                 line: value.line,
                 col: value.col,
+                span: value.span,
             },
             crate::scanner::TokenType::Plus => Self {
                 ty: BinaryOpTy::Plus,
                 line: value.line,
                 col: value.col,
+                span: value.span,
             },
             crate::scanner::TokenType::Slash => Self {
                 ty: BinaryOpTy::Slash,
                 line: value.line,
                 col: value.col,
+                span: value.span,
             },
             crate::scanner::TokenType::Star => Self {
                 ty: BinaryOpTy::Star,
                 line: value.line,
                 col: value.col,
+                span: value.span,
             },
             crate::scanner::TokenType::BangEqual => Self {
                 ty: BinaryOpTy::NotEqual,
                 line: value.line,
                 col: value.col,
+                span: value.span,
             },
             crate::scanner::TokenType::EqualEqual => Self {
                 ty: BinaryOpTy::EqualEqual,
                 line: value.line,
                 col: value.col,
+                span: value.span,
             },
             crate::scanner::TokenType::Greater => Self {
                 ty: BinaryOpTy::Greater,
                 line: value.line,
                 col: value.col,
+                span: value.span,
             },
             crate::scanner::TokenType::GreaterEqual => Self {
                 ty: BinaryOpTy::GreaterEqual,
                 line: value.line,
                 col: value.col,
+                span: value.span,
             },
             crate::scanner::TokenType::Less => Self {
                 ty: BinaryOpTy::Less,
                 line: value.line,
                 col: value.col,
+                span: value.span,
             },
             crate::scanner::TokenType::LessEqual => Self {
                 ty: BinaryOpTy::LessEqual,
                 line: value.line,
                 col: value.col,
+                span: value.span,
             },
             _ => panic!(
                 "this was not supposed to happen! This token `{value:?}` is not a `BinaryOpTy`"
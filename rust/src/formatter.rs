@@ -0,0 +1,274 @@
+//! Reformats Lox source in one consistent layout: one statement per line,
+//! blocks indented by four spaces, single spaces around binary operators and
+//! none inside brackets. Works on the token stream, so comments survive, and
+//! keeps at most one blank line wherever the source had some.
+
+use crate::scanner::{Token, TokenType};
+
+const INDENT: &str = "    ";
+
+/// Formats `tokens`, which must come from `scan_tokens_with_comments` on
+/// source that parses.
+pub fn format(tokens: &[Token]) -> String {
+    let mut formatter = Formatter::default();
+    let mut i = 0;
+    while i < tokens.len() && tokens[i].ty != TokenType::Eof {
+        let token = &tokens[i];
+        // An empty block stays on one line.
+        if token.ty == TokenType::LeftBrace
+            && formatter.opens_block()
+            && tokens.get(i + 1).map(|next| next.ty) == Some(TokenType::RightBrace)
+        {
+            formatter.write(token, "{}");
+            formatter.close_block(tokens.get(i + 2));
+            formatter.last = Some(tokens[i + 1].clone());
+            i += 2;
+            continue;
+        }
+        formatter.token(token, tokens.get(i + 1));
+        i += 1;
+    }
+    formatter.out.truncate(formatter.out.trim_end().len());
+    formatter.out.push('\n');
+    return formatter.out;
+}
+
+/// What a bracket that is still open encloses.
+#[derive(Clone, Copy, PartialEq)]
+enum Context {
+    /// Statements, between the braces of a block, function or class.
+    Block,
+    /// The entries of a map literal.
+    Map,
+    /// Arguments, parameters, a grouping or list elements.
+    Group,
+}
+
+#[derive(Default)]
+struct Formatter {
+    out: String,
+    contexts: Vec<Context>,
+    /// The token written last.
+    last: Option<Token>,
+    /// Whether the last token was an operand, so a following `-` subtracts
+    /// rather than negates.
+    last_was_operand: bool,
+    /// Whether the last token was a prefix `-` or `!`.
+    last_was_unary: bool,
+    /// Set when the current line is finished. The break is written before
+    /// the next token so a trailing comment can still join the line.
+    pending_newline: bool,
+}
+
+impl Formatter {
+    fn token(&mut self, token: &Token, next: Option<&Token>) {
+        let text = String::from_utf8_lossy(&token.lexeme)
+            .trim_end()
+            .to_string();
+        let mut closed_map = false;
+        match token.ty {
+            TokenType::Comment => {
+                let trailing = self.pending_newline
+                    && self
+                        .last
+                        .as_ref()
+                        .is_some_and(|last| end_line(last) == token.line);
+                if trailing {
+                    self.out.push(' ');
+                    self.out.push_str(&text);
+                } else {
+                    self.break_line(token);
+                    self.indent();
+                    self.out.push_str(&text);
+                }
+                self.pending_newline = true;
+                self.last = Some(token.clone());
+                return;
+            }
+            TokenType::LeftBrace if self.opens_block() => {
+                self.write(token, "{");
+                self.contexts.push(Context::Block);
+                self.pending_newline = true;
+            }
+            TokenType::LeftBrace => {
+                self.write(token, "{");
+                self.contexts.push(Context::Map);
+            }
+            TokenType::RightBrace if self.contexts.last() == Some(&Context::Map) => {
+                self.write(token, "}");
+                self.contexts.pop();
+                closed_map = true;
+            }
+            TokenType::RightBrace => {
+                self.contexts.pop();
+                self.pending_newline = true;
+                self.write(token, "}");
+                self.close_block(next);
+            }
+            TokenType::LeftParen | TokenType::LeftBracket => {
+                self.write(token, &text);
+                self.contexts.push(Context::Group);
+            }
+            TokenType::RightParen | TokenType::RightBracket => {
+                self.contexts.pop();
+                self.write(token, &text);
+            }
+            TokenType::Semicolon => {
+                self.write(token, ";");
+                // The `;`s in a `for` header don't end the line.
+                if self.at_statement_level() {
+                    self.pending_newline = true;
+                }
+            }
+            _ => self.write(token, &text),
+        }
+        self.last_was_unary = match token.ty {
+            TokenType::Bang => true,
+            TokenType::Minus => !self.last_was_operand,
+            _ => false,
+        };
+        self.last_was_operand = match token.ty {
+            TokenType::Identifier
+            | TokenType::Number
+            | TokenType::String
+            | TokenType::True
+            | TokenType::False
+            | TokenType::Nil
+            | TokenType::This
+            | TokenType::RightParen
+            | TokenType::RightBracket => true,
+            TokenType::RightBrace => closed_map,
+            _ => false,
+        };
+        self.last = Some(token.clone());
+    }
+
+    /// Writes `text` for `token`, first finishing the line if one is pending.
+    fn write(&mut self, token: &Token, text: &str) {
+        if self.pending_newline {
+            self.break_line(token);
+        }
+        if self.out.is_empty() || self.out.ends_with('\n') {
+            self.indent();
+        } else if self.space_before(token) {
+            self.out.push(' ');
+        }
+        self.out.push_str(text);
+    }
+
+    /// Ends the current line before `token`, keeping one blank line if the
+    /// source had any between them.
+    fn break_line(&mut self, token: &Token) {
+        self.pending_newline = false;
+        if self.out.is_empty() {
+            return;
+        }
+        if !self.out.ends_with('\n') {
+            self.out.push('\n');
+        }
+        let Some(last) = &self.last else {
+            return;
+        };
+        let blank = token.line > end_line(last) + 1
+            && last.ty != TokenType::LeftBrace
+            && token.ty != TokenType::RightBrace;
+        if blank {
+            self.out.push('\n');
+        }
+    }
+
+    fn indent(&mut self) {
+        for _ in 0..self
+            .contexts
+            .iter()
+            .filter(|c| **c == Context::Block)
+            .count()
+        {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    /// Decides what follows the `}` of a block: `else`, `catch`, `finally`
+    /// and punctuation stay on its line, anything else starts a new one.
+    fn close_block(&mut self, next: Option<&Token>) {
+        self.pending_newline = !matches!(
+            next.map(|next| next.ty),
+            Some(
+                TokenType::Else
+                    | TokenType::Catch
+                    | TokenType::Finally
+                    | TokenType::Semicolon
+                    | TokenType::RightParen
+                    | TokenType::RightBracket
+                    | TokenType::Comma
+            )
+        );
+        self.last_was_operand = false;
+        self.last_was_unary = false;
+    }
+
+    fn at_statement_level(&self) -> bool {
+        return matches!(self.contexts.last(), None | Some(Context::Block));
+    }
+
+    /// Whether a `{` written now starts a block rather than a map literal.
+    /// Blocks follow the end of a statement, a function's parameters, a
+    /// class name or one of the keywords that take a body.
+    fn opens_block(&self) -> bool {
+        let Some(last) = &self.last else {
+            return true;
+        };
+        return match last.ty {
+            TokenType::RightParen
+            | TokenType::Identifier
+            | TokenType::Else
+            | TokenType::Try
+            | TokenType::Finally
+            | TokenType::Semicolon
+            | TokenType::Comment => true,
+            TokenType::LeftBrace | TokenType::RightBrace => self.at_statement_level(),
+            _ => false,
+        };
+    }
+
+    fn space_before(&self, token: &Token) -> bool {
+        let Some(last) = &self.last else {
+            return false;
+        };
+        let hugs_next = matches!(
+            last.ty,
+            TokenType::LeftParen | TokenType::LeftBracket | TokenType::Dot | TokenType::Ellipsis
+        ) || (last.ty == TokenType::LeftBrace
+            && self.contexts.last() == Some(&Context::Map));
+        if self.last_was_unary || hugs_next {
+            return false;
+        }
+        match token.ty {
+            TokenType::RightParen
+            | TokenType::RightBracket
+            | TokenType::Comma
+            | TokenType::Semicolon
+            | TokenType::Dot
+            | TokenType::Colon
+            | TokenType::RightBrace => return false,
+            // A call or a subscript hugs what it applies to.
+            TokenType::LeftParen | TokenType::LeftBracket => {
+                return !matches!(
+                    last.ty,
+                    TokenType::Identifier
+                        | TokenType::RightParen
+                        | TokenType::RightBracket
+                        | TokenType::This
+                        | TokenType::Super
+                        | TokenType::String
+                );
+            }
+            _ => return true,
+        }
+    }
+}
+
+/// The line `token` ends on; strings can span several.
+fn end_line(token: &Token) -> usize {
+    return token.line + token.lexeme.iter().filter(|b| **b == b'\n').count();
+}
@@ -1,26 +1,86 @@
-use std::time::SystemTime;
-use std::{collections::HashMap, time::UNIX_EPOCH};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
 
-use crate::expr::LoxFunction;
+use crate::expr::{
+    bind_method, Arity, LoxClass, LoxFunction, LoxInstance, LoxMap, MapKey, NativeFunction,
+};
+use crate::natives::{self, NativeContext};
+use crate::output::Output;
+use crate::resolver::{keyword_symbol, Locals};
 use crate::{
     env::Environment,
-    error::Error,
+    error::{Error, StackFrame},
     expr::{
-        BinaryOp, BinaryOpTy, Expr, Literal, LogicalOp, NativeFunction, Stmt, Symbol, UnaryOp,
-        UnaryOpTy, Value,
+        BinaryOp, BinaryOpTy, Expr, ExprKind, FunDecl, Literal, LogicalOp, SourceLocation, Stmt,
+        StmtKind, Symbol, UnaryOp, UnaryOpTy, Value,
     },
 };
 
+/// Calls nested deeper than this raise a runtime error instead of growing the
+/// frame stack without bound. The script itself counts as one, and natives,
+/// which never call back into Lox, don't count. The VM has the same limit.
+pub(crate) const FRAMES_MAX: usize = 4096;
+/// When less native stack than this is left at a call, the call runs on a
+/// fresh segment of `STACK_SEGMENT` bytes.
+const STACK_RED_ZONE: usize = 256 * 1024;
+const STACK_SEGMENT: usize = 4 * 1024 * 1024;
+
 pub trait Callable {
-    fn call(&self, interpreter: &mut Interpreter, arguments: &[Value]) -> Result<Value, String>;
-    fn arity(&self, interpreter: &Interpreter) -> u8;
+    fn call(&self, interpreter: &mut Interpreter, arguments: &[Value]) -> Result<Value, Error>;
+    fn arity(&self, interpreter: &Interpreter) -> Arity;
 }
 
 pub struct Interpreter {
-    pub globals: Environment,
-    pub env: Environment,
-    pub ret_val: Option<Value>,
-    loop_stack: Vec<String>,
+    pub globals: Rc<RefCell<Environment>>,
+    pub env: Rc<RefCell<Environment>>,
+    pub locals: HashMap<Symbol, usize>,
+    /// Entries of `locals` for the top-level code of the program being run,
+    /// dropped once it finishes. Functions keep theirs since they can be
+    /// called later, e.g. from the next REPL input.
+    top_level: Vec<Symbol>,
+    /// Class of the values that runtime errors are turned into when a
+    /// `catch` block receives them.
+    runtime_error_class: Rc<LoxClass>,
+    /// Calls currently being executed, outermost first.
+    frames: Vec<CallFrame>,
+    /// Set by the `exit` native. The call that ran it then fails with
+    /// `Error::Exit`.
+    pub(crate) exit_code: Option<u8>,
+    /// Where `print` writes. Flushed whenever `interpret` or `call` returns.
+    pub output: Output,
+}
+
+/// Why a statement stopped before reaching its end: an error, or a jump to
+/// the enclosing loop or call made by `break`, `continue` or `return`.
+pub(crate) enum Unwind {
+    Error(Error),
+    Break(Option<String>),
+    Continue(Option<String>),
+    Return(Value),
+}
+
+impl From<Error> for Unwind {
+    fn from(error: Error) -> Self {
+        return Unwind::Error(error);
+    }
+}
+
+impl Unwind {
+    /// The error this is, for code where the parser has ruled out jumps.
+    pub(crate) fn into_error(self) -> Error {
+        match self {
+            Unwind::Error(error) => return error,
+            Unwind::Break(_) | Unwind::Continue(_) | Unwind::Return(_) => {
+                unreachable!("the parser keeps jumps inside loops and functions")
+            }
+        }
+    }
+}
+
+struct CallFrame {
+    function: String,
+    call_site: SourceLocation,
 }
 
 impl Default for Interpreter {
@@ -29,193 +89,620 @@ impl Default for Interpreter {
             values: HashMap::new(),
             enclosing: None,
         };
-        let clock_sym = Symbol {
-            name: "clock".to_string(),
-            line: 0,
-            col: -1,
-        };
-        let clock = NativeFunction {
-            name: clock_sym.name.clone(),
-            arity: 0,
-            callable: |_, _| {
-                return Ok(Value::Number(
-                    SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .expect("Time went backwards.")
-                        .as_secs() as f64,
-                ));
-            },
-        };
-        globals.define(&clock_sym, Value::NativeFunction(clock));
+        natives::define_natives(&mut globals);
+        let globals = Rc::new(RefCell::new(globals));
         return Self {
             globals: globals.clone(),
             env: globals,
-            loop_stack: vec![],
-            ret_val: None,
+            locals: HashMap::new(),
+            top_level: Vec::new(),
+            runtime_error_class: runtime_error_class(),
+            frames: Vec::new(),
+            exit_code: None,
+            output: Output::default(),
+        };
+    }
+}
+
+impl NativeContext for Interpreter {
+    fn get_global(&self, name: &str) -> Option<Value> {
+        return self.globals.borrow().values.get(name).cloned();
+    }
+
+    fn set_global(&mut self, name: &str, value: Value) {
+        self.globals
+            .borrow_mut()
+            .values
+            .insert(name.to_string(), value);
+    }
+
+    fn exit(&mut self, code: u8) {
+        self.exit_code = Some(code);
+    }
+}
+
+/// Builds an interpreter with extra native functions defined as globals.
+///
+/// ```
+/// use std::{cell::Cell, rc::Rc};
+/// use rust_interpreter::{Interpreter, Value};
+///
+/// let calls = Rc::new(Cell::new(0));
+/// let counter = calls.clone();
+/// let interpreter = Interpreter::builder()
+///     .native("log", 1, move |_, args| {
+///         counter.set(counter.get() + 1);
+///         println!("{}", args[0]);
+///         Ok(Value::Nil)
+///     })
+///     .variadic_native("sum", 0, |_, args| {
+///         let mut total = 0.0;
+///         for arg in args {
+///             match arg {
+///                 Value::Number(n) => total += n,
+///                 other => return Err(format!("Can't add {other}.")),
+///             }
+///         }
+///         Ok(Value::Number(total))
+///     })
+///     .build();
+///
+/// let mut lox = rust_interpreter::Lox::from(interpreter);
+/// lox.eval("log(sum(1, 2, 3));").unwrap();
+/// assert_eq!(calls.get(), 1);
+/// ```
+#[derive(Default)]
+pub struct InterpreterBuilder {
+    natives: Vec<NativeFunction>,
+    output: Output,
+}
+
+impl InterpreterBuilder {
+    /// Registers `callable` as a global function taking exactly `arity`
+    /// arguments.
+    pub fn native(
+        mut self,
+        name: &str,
+        arity: u8,
+        callable: impl Fn(&mut dyn NativeContext, &[Value]) -> Result<Value, String> + 'static,
+    ) -> Self {
+        self.natives
+            .push(NativeFunction::new(name, arity, callable));
+        return self;
+    }
+
+    /// Registers `callable` as a global function taking `min_arity` or more
+    /// arguments.
+    pub fn variadic_native(
+        mut self,
+        name: &str,
+        min_arity: u8,
+        callable: impl Fn(&mut dyn NativeContext, &[Value]) -> Result<Value, String> + 'static,
+    ) -> Self {
+        self.natives
+            .push(NativeFunction::variadic(name, min_arity, callable));
+        return self;
+    }
+
+    /// Sends everything the script prints to `output` instead of stdout.
+    pub fn output(mut self, output: impl Into<Output>) -> Self {
+        self.output = output.into();
+        return self;
+    }
+
+    pub fn build(self) -> Interpreter {
+        let mut interpreter = Interpreter {
+            output: self.output,
+            ..Interpreter::default()
         };
+        for native in self.natives {
+            let name = native.name.clone();
+            interpreter.set_global(&name, Value::NativeFunction(native));
+        }
+        return interpreter;
     }
 }
 
 impl Interpreter {
-    pub fn interpret(&mut self, stmts: &[Stmt]) -> Result<(), Error> {
+    pub fn builder() -> InterpreterBuilder {
+        return InterpreterBuilder::default();
+    }
+
+    pub fn resolve(&mut self, locals: Locals) {
+        self.top_level.extend(locals.top_level.keys().cloned());
+        self.locals.extend(locals.top_level);
+        self.locals.extend(locals.in_functions);
+    }
+
+    fn look_up_variable(&self, name: &Symbol) -> Result<Value, Error> {
+        match self.locals.get(name) {
+            Some(distance) => self.env.borrow().get_at(*distance, name),
+            None => self.globals.borrow().get(name),
+        }
+    }
+
+    /// Runs `stmts` and returns the value of the last one when it is an
+    /// expression statement, or `nil` otherwise.
+    pub fn interpret(&mut self, stmts: &[Stmt]) -> Result<Value, Error> {
+        let result = self.run(stmts);
+        for symbol in self.top_level.drain(..) {
+            self.locals.remove(&symbol);
+        }
+        return self.output.finish(result);
+    }
+
+    fn run(&mut self, stmts: &[Stmt]) -> Result<Value, Error> {
+        let mut value = Value::Nil;
         for stmt in stmts {
-            if let Err(res) = self.evaluate(stmt) {
-                return Err(res);
+            let res = match &stmt.kind {
+                StmtKind::Expr(expr) => self.get_value(expr).map(|v| value = v),
+                _ => self
+                    .evaluate(stmt)
+                    .map(|_| value = Value::Nil)
+                    .map_err(Unwind::into_error),
+            };
+            if let Err(e) = res {
+                return Err(self.uncaught(e));
             }
         }
-        Ok(())
+        return Ok(value);
+    }
+
+    /// Calls `callee` from outside any script, e.g. on behalf of an embedder.
+    pub fn call(&mut self, callee: Value, arguments: &[Value]) -> Result<Value, Error> {
+        let location = SourceLocation::none();
+        let result = self
+            .call_value(callee, arguments, &location)
+            .map_err(|e| self.uncaught(e));
+        return self.output.finish(result);
+    }
+
+    fn uncaught(&mut self, error: Error) -> Error {
+        let error = self.with_trace(error);
+        self.frames.clear();
+        return error;
+    }
+
+    /// Attaches the current call stack to an exception the first time it
+    /// unwinds out of a call, so the trace reflects where it was raised.
+    fn with_trace(&self, error: Error) -> Error {
+        if !error.is_exception() {
+            return error;
+        }
+        if let Error::Traced { .. } = error {
+            return error;
+        }
+        let mut line = error.line().unwrap_or(0);
+        let mut trace = Vec::with_capacity(self.frames.len() + 1);
+        for frame in self.frames.iter().rev() {
+            trace.push(StackFrame {
+                function: frame.function.clone(),
+                line,
+            });
+            line = frame.call_site.line;
+        }
+        trace.push(StackFrame {
+            function: "<script>".to_string(),
+            line,
+        });
+        return Error::Traced {
+            error: Box::new(error),
+            trace,
+        };
     }
 
-    pub fn evaluate(&mut self, stmt: &Stmt) -> Result<(), Error> {
-        match stmt {
-            Stmt::Expr(v) => match self.get_value(v) {
+    pub(crate) fn evaluate(&mut self, stmt: &Stmt) -> Result<(), Unwind> {
+        match &stmt.kind {
+            StmtKind::Expr(v) => match self.get_value(v) {
                 Ok(_) => {
                     return Ok(());
                 }
-                Err(e) => return Err(e),
+                Err(e) => return Err(e.into()),
             },
-            Stmt::Print(v) => {
+            StmtKind::Print(v) => {
                 let value = self.get_value(v)?;
-                println!("The value is: {0}", value);
-                return Ok(());
+                return Ok(self.output.print(&value, stmt.location)?);
             }
-            Stmt::VarDecl(sym, expr) => match expr {
+            StmtKind::VarDecl(sym, expr) => match expr {
                 Some(expr) => {
                     let value = self.get_value(expr)?;
-                    self.env.define(sym, value.clone());
+                    self.env.borrow_mut().define(sym, value.clone());
                     return Ok(());
                 }
                 None => {
-                    self.env.define(sym, Value::Undefined);
+                    self.env.borrow_mut().define(sym, Value::Undefined);
                     return Ok(());
                 }
             },
-            Stmt::Block(statements) => {
+            StmtKind::Block(statements) => {
                 let environment = Environment::with_enclosing(self.env.clone());
-                return self.interpret_block(statements, environment);
-            }
-            Stmt::While(condition, while_stmt) => {
-                let id = SystemTime::now()
-                    .duration_since(SystemTime::UNIX_EPOCH)
-                    .expect("Time went backwards")
-                    .as_secs()
-                    .to_string();
-                self.loop_stack.push(id.clone());
-                while is_truthy(&self.get_value(condition)?)?
-                    && self.loop_stack.last().unwrap_or(&"None".to_string()) == &id
-                {
-                    self.evaluate(while_stmt)?;
+                return self.interpret_block(statements, Rc::new(RefCell::new(environment)));
+            }
+            StmtKind::While {
+                condition,
+                body,
+                increment,
+                label,
+            } => {
+                let label = label.as_ref().map(|label| &label.name);
+                while is_truthy(&self.get_value(condition)?, &condition.location)? {
+                    match self.evaluate(body) {
+                        Ok(()) => {}
+                        Err(Unwind::Break(target))
+                            if target.is_none() || target.as_ref() == label =>
+                        {
+                            break;
+                        }
+                        Err(Unwind::Continue(target))
+                            if target.is_none() || target.as_ref() == label => {}
+                        Err(e) => return Err(e),
+                    }
+                    if let Some(increment) = increment {
+                        self.get_value(increment)?;
+                    }
                 }
                 return Ok(());
             }
-            Stmt::If(condition, if_stmt, else_stmt) => {
-                if is_truthy(&self.get_value(condition)?)? {
+            StmtKind::Throw(location, value) => {
+                let value = self.get_value(value)?;
+                return Err(Unwind::Error(Error::Throw {
+                    value,
+                    location: *location,
+                }));
+            }
+            StmtKind::Try {
+                body,
+                catch,
+                finally,
+            } => {
+                let environment = Environment::with_enclosing(self.env.clone());
+                let mut res = self.interpret_block(body, Rc::new(RefCell::new(environment)));
+
+                if let (Err(Unwind::Error(e)), Some((name, catch_body))) = (&res, catch) {
+                    if let Some(exception) = self.exception_value(e) {
+                        let mut environment = Environment::with_enclosing(self.env.clone());
+                        environment.define(name, exception);
+                        res = self.interpret_block(catch_body, Rc::new(RefCell::new(environment)));
+                    }
+                }
+
+                // `exit` stops the script at once, as it does on the VM.
+                if let Err(Unwind::Error(Error::Exit { .. })) = res {
+                    return res;
+                }
+                if let Some(finally_body) = finally {
+                    let environment = Environment::with_enclosing(self.env.clone());
+                    self.interpret_block(finally_body, Rc::new(RefCell::new(environment)))?;
+                }
+                return res;
+            }
+            StmtKind::Break(_, label) => {
+                return Err(Unwind::Break(
+                    label.as_ref().map(|label| label.name.clone()),
+                ));
+            }
+            StmtKind::Continue(_, label) => {
+                return Err(Unwind::Continue(
+                    label.as_ref().map(|label| label.name.clone()),
+                ));
+            }
+            StmtKind::If(condition, if_stmt, else_stmt) => {
+                if is_truthy(&self.get_value(condition)?, &condition.location)? {
                     self.evaluate(if_stmt)?;
                 } else if let Some(else_stmt) = else_stmt {
                     self.evaluate(else_stmt)?;
                 }
                 return Ok(());
             }
-            Stmt::FunDecl(fun) => {
+            StmtKind::FunDecl(fun) => {
                 let func = LoxFunction {
-                    declaration: Stmt::FunDecl(fun.clone()),
+                    declaration: Rc::new(fun.clone()),
+                    closure: self.env.clone(),
+                    is_initializer: false,
+                };
+                self.env
+                    .borrow_mut()
+                    .define(&fun.name, Value::LoxFunction(func));
+                return Ok(());
+            }
+            StmtKind::ClassDecl(class_decl) => {
+                let superclass = match &class_decl.superclass {
+                    Some(superclass_sym) => {
+                        let superclass = self.look_up_variable(superclass_sym)?;
+                        match superclass {
+                            Value::Class(superclass) => Some(superclass),
+                            _ => {
+                                return Err(Unwind::Error(Error::RunTimeException {
+                                    message: "Superclass must be a class.".to_string(),
+                                    location: superclass_sym.location(),
+                                }))
+                            }
+                        }
+                    }
+                    None => None,
+                };
+                let closure = match &superclass {
+                    Some(superclass) => {
+                        let mut env = Environment::with_enclosing(self.env.clone());
+                        env.values
+                            .insert("super".to_string(), Value::Class(superclass.clone()));
+                        Rc::new(RefCell::new(env))
+                    }
+                    None => self.env.clone(),
                 };
-                self.env.define(&fun.name, Value::LoxFunction(func));
+                let methods = class_decl
+                    .methods
+                    .iter()
+                    .map(|method| {
+                        let func = LoxFunction {
+                            declaration: Rc::new(method.clone()),
+                            closure: closure.clone(),
+                            is_initializer: method.name.name == "init",
+                        };
+                        (method.name.name.clone(), Value::LoxFunction(func))
+                    })
+                    .collect();
+                let class = LoxClass {
+                    name: class_decl.name.name.clone(),
+                    superclass,
+                    methods,
+                };
+                self.env
+                    .borrow_mut()
+                    .define(&class_decl.name, Value::Class(Rc::new(class)));
                 return Ok(());
             }
-            Stmt::Return(_, expr) => {
+            StmtKind::Return(_, expr) => {
                 let mut value = Value::Nil;
                 if let Some(expr) = expr {
                     value = self.get_value(expr)?;
                 }
-                self.ret_val = Some(value.clone());
-                return Err(Error::Return { value });
+                return Err(Unwind::Return(value));
             }
-            _ => return Ok(()),
         }
     }
 
     pub fn get_value(&mut self, expr: &Expr) -> Result<Value, Error> {
-        match expr {
-            Expr::Literal(val) => match val {
+        match &expr.kind {
+            ExprKind::Literal(val) => match val {
                 Literal::Number(val) => Ok(Value::Number(*val)),
                 Literal::String(val) => Ok(Value::String(val.to_string())),
                 Literal::True => Ok(Value::Bool(true)),
-                Literal::False => Ok(Value::Bool(true)),
+                Literal::False => Ok(Value::Bool(false)),
                 Literal::Nil => Ok(Value::Nil),
             },
-            Expr::Unary(op, expr) => self.interpret_unary(op, expr),
-            Expr::Binary(left_expr, op, right_expr) => {
+            ExprKind::Unary(op, expr) => self.interpret_unary(op, expr),
+            ExprKind::Binary(left_expr, op, right_expr) => {
                 self.interpret_binary(left_expr, right_expr, op)
             }
-            Expr::Grouping(expr) => return self.get_value(expr),
-            Expr::Assign(symbol, expr) => {
+            ExprKind::Grouping(expr) => return self.get_value(expr),
+            ExprKind::Assign(symbol, expr) => {
                 let value = self.get_value(expr)?;
-                let _ = self.env.assign(symbol, value.clone());
+                match self.locals.get(symbol) {
+                    Some(distance) => {
+                        self.env
+                            .borrow_mut()
+                            .assign_at(*distance, symbol, value.clone())?
+                    }
+                    None => self.globals.borrow_mut().assign(symbol, value.clone())?,
+                }
                 return Ok(value);
             }
-            Expr::Variable(v) => {
-                let value = self.env.get(&v)?;
+            ExprKind::Variable(v) => {
+                let value = self.look_up_variable(v)?;
                 if let Value::Undefined = value {
                     return Err(Error::UndefinedVariable {
                         name: v.name.clone(),
-                        line: v.line,
-                        col: v.col,
+                        location: v.location(),
                     });
                 }
-                return self.env.get(&v);
+                return Ok(value);
             }
-            Expr::Logical(left, op, right) => {
+            ExprKind::Logical(left, op, right) => {
                 return self.interpret_logical(left, op, right);
             }
-            Expr::Call(callee, location, args) => {
+            ExprKind::Call(callee, location, args) => {
                 let callee = self.get_value(callee)?;
                 let mut arguments = Vec::new();
                 for arg in args {
-                    arguments.push(self.get_value(arg)?);
-                }
-                match callee {
-                    Value::NativeFunction(callee) => {
-                        let res = callee.call(self, arguments.as_slice());
-                        match res {
-                            Ok(v) => Ok(v),
-                            Err(e) => Err(Error::JustError { message: e }),
+                    match &arg.kind {
+                        ExprKind::Spread(list) => {
+                            let list = self.get_value(list)?;
+                            arguments.extend(spread(&list, location)?);
                         }
+                        _ => arguments.push(self.get_value(arg)?),
                     }
-                    Value::LoxFunction(callee) => {
-                        let res = callee.call(self, arguments.as_slice());
-                        match res {
-                            Ok(v) => Ok(v),
-                            Err(e) => Err(Error::JustError { message: e }),
-                        }
+                }
+                return self.call_value(callee, &arguments, location);
+            }
+            ExprKind::Get(object, name) => match self.get_value(object)? {
+                Value::Instance(instance) => return LoxInstance::get(&instance, name),
+                _ => {
+                    return Err(Error::RunTimeException {
+                        message: "Only instances have properties.".to_string(),
+                        location: name.location(),
+                    })
+                }
+            },
+            ExprKind::Set(object, name, value) => match self.get_value(object)? {
+                Value::Instance(instance) => {
+                    let value = self.get_value(value)?;
+                    instance.borrow_mut().set(name, value.clone());
+                    return Ok(value);
+                }
+                _ => {
+                    return Err(Error::RunTimeException {
+                        message: "Only instances have fields.".to_string(),
+                        location: name.location(),
+                    })
+                }
+            },
+            ExprKind::This(location) => {
+                return self.look_up_variable(&keyword_symbol("this", location));
+            }
+            ExprKind::List(elements) => {
+                let mut values = Vec::with_capacity(elements.len());
+                for element in elements {
+                    values.push(self.get_value(element)?);
+                }
+                return Ok(Value::List(Rc::new(RefCell::new(values))));
+            }
+            ExprKind::Map(entries, source_location) => {
+                let mut map = LoxMap::default();
+                for (key, value) in entries {
+                    let key = self.get_value(key)?;
+                    let key = map_key(&key, source_location)?;
+                    let value = self.get_value(value)?;
+                    map.insert(key, value);
+                }
+                return Ok(Value::Map(Rc::new(RefCell::new(map))));
+            }
+            ExprKind::Subscript {
+                value,
+                slice,
+                source_location,
+            } => {
+                let value = self.get_value(value)?;
+                let slice = self.get_value(slice)?;
+                return get_item(value, slice, source_location);
+            }
+            ExprKind::SetItem {
+                lhs,
+                slice,
+                rhs,
+                source_location,
+            } => {
+                let lhs = self.get_value(lhs)?;
+                let slice = self.get_value(slice)?;
+                let rhs = self.get_value(rhs)?;
+                return set_item(lhs, slice, rhs, source_location);
+            }
+            ExprKind::Lambda(lambda_decl) => {
+                let fun_decl = FunDecl {
+                    name: keyword_symbol("lambda", &lambda_decl.keyword),
+                    params: lambda_decl.params.clone(),
+                    rest: lambda_decl.rest.clone(),
+                    body: lambda_decl.body.clone(),
+                };
+                return Ok(Value::LoxFunction(LoxFunction {
+                    declaration: Rc::new(fun_decl),
+                    closure: self.env.clone(),
+                    is_initializer: false,
+                }));
+            }
+            ExprKind::Spread(_) => unreachable!("the parser only allows spreads in call arguments"),
+            ExprKind::Super(location, method) => {
+                let super_sym = keyword_symbol("super", location);
+                // The resolver puts `super` in its own scope around the one
+                // holding `this`, so a `super` it missed, or one with no
+                // scope inside it, is a bug rather than a script error.
+                let distances = self.locals.get(&super_sym).copied().and_then(|distance| {
+                    return Some((distance, distance.checked_sub(1)?));
+                });
+                let Some((distance, this_distance)) = distances else {
+                    return Err(Error::RunTimeException {
+                        message: "Internal error: `super` was not resolved.".to_string(),
+                        location: *location,
+                    });
+                };
+                let superclass = self.env.borrow().get_at(distance, &super_sym)?;
+                let superclass = match superclass {
+                    Value::Class(superclass) => superclass,
+                    _ => {
+                        return Err(Error::RunTimeException {
+                            message: "`super` is not bound to a class.".to_string(),
+                            location: *location,
+                        })
                     }
+                };
+                let this_sym = keyword_symbol("this", location);
+                let instance = self.env.borrow().get_at(this_distance, &this_sym)?;
+                let instance = match instance {
+                    Value::Instance(instance) => instance,
                     _ => {
                         return Err(Error::RunTimeException {
-                            message: "Can only call function and classes".to_string(),
-                            line: location.line,
-                            col: location.col,
+                            message: "`this` is not bound to an instance.".to_string(),
+                            location: *location,
+                        })
+                    }
+                };
+                match superclass.find_method(&method.name) {
+                    Some(method) => return Ok(bind_method(method, instance)),
+                    None => {
+                        return Err(Error::RunTimeException {
+                            message: format!("Undefined property `{0}`.", method.name),
+                            location: method.location(),
                         })
                     }
                 }
             }
-            _ => return Ok(Value::Nil),
         }
     }
 
+    fn call_value(
+        &mut self,
+        callee: Value,
+        arguments: &[Value],
+        location: &SourceLocation,
+    ) -> Result<Value, Error> {
+        let (function, arity) = match &callee {
+            Value::NativeFunction(callee) => (callee.name.clone(), callee.arity(self)),
+            Value::LoxFunction(callee) => (callee.name().to_string(), callee.arity(self)),
+            Value::Class(callee) => (callee.name.clone(), callee.arity(self)),
+            _ => {
+                return Err(Error::RunTimeException {
+                    message: "Can only call function and classes".to_string(),
+                    location: *location,
+                })
+            }
+        };
+        let native = matches!(callee, Value::NativeFunction(_));
+        if !native && self.frames.len() + 1 >= FRAMES_MAX {
+            return Err(Error::RunTimeException {
+                message: "Stack overflow.".to_string(),
+                location: *location,
+            });
+        }
+        check_arity(arity, arguments.len(), location)?;
+        self.frames.push(CallFrame {
+            function,
+            call_site: *location,
+        });
+        // A tree-walking call takes far more native stack than a VM frame,
+        // so grow the stack as needed to reach the same depth.
+        let res = stacker::maybe_grow(STACK_RED_ZONE, STACK_SEGMENT, || match callee {
+            Value::NativeFunction(callee) => match callee.call(self, arguments) {
+                Ok(v) => Ok(v),
+                Err(Error::JustError { message }) => Err(Error::RunTimeException {
+                    message,
+                    location: *location,
+                }),
+                Err(e) => Err(e),
+            },
+            Value::LoxFunction(callee) => callee.call(self, arguments),
+            Value::Class(callee) => callee.call(self, arguments),
+            _ => unreachable!("non-callable values are rejected above"),
+        });
+        let res = res.map_err(|e| self.with_trace(e));
+        self.frames.pop();
+        return res;
+    }
+
     fn interpret_logical(
         &mut self,
-        left: &Box<Expr>,
+        left: &Expr,
         op: &LogicalOp,
-        right: &Box<Expr>,
+        right: &Expr,
     ) -> Result<Value, Error> {
+        let location = left.location;
         let left = self.get_value(left)?;
         if let LogicalOp::Or = op {
-            if is_truthy(&left)? {
+            if is_truthy(&left, &location)? {
                 return Ok(left);
             }
         }
         if let LogicalOp::And = op {
-            if !is_truthy(&left)? {
+            if !is_truthy(&left, &location)? {
                 return Ok(left);
             }
         }
@@ -223,23 +710,8 @@ impl Interpreter {
     }
 
     fn interpret_unary(&mut self, op: &UnaryOp, right: &Expr) -> Result<Value, Error> {
-        let right: Value = self.get_value(right)?;
-        match op.ty {
-            UnaryOpTy::Minus => {
-                if let Value::Number(v) = right {
-                    return Ok(Value::Number(-v));
-                }
-                let message = format!("Wrong Unary Token In {op:?} with {right:?}");
-                return Err(Error::RunTimeException {
-                    message: String::from(message),
-                    line: op.line,
-                    col: op.col,
-                });
-            }
-            UnaryOpTy::Bang => {
-                return Ok(Value::Bool(is_truthy(&right)?));
-            }
-        }
+        let right = self.get_value(right)?;
+        return unary_op(op, right);
     }
 
     fn interpret_binary(
@@ -248,112 +720,285 @@ impl Interpreter {
         right_expr: &Expr,
         op: &BinaryOp,
     ) -> Result<Value, Error> {
-        let right = self.get_value(right_expr)?;
         let left = self.get_value(left_expr)?;
+        let right = self.get_value(right_expr)?;
+        return binary_op(op, left, right);
+    }
 
-        if let Value::Number(l) = left {
-            if let Value::Number(r) = right {
-                match op.ty {
-                    BinaryOpTy::EqualEqual => return Ok(Value::Bool(l == r)),
-                    BinaryOpTy::NotEqual => return Ok(Value::Bool(l != r)),
-                    BinaryOpTy::Less => return Ok(Value::Bool(l < r)),
-                    BinaryOpTy::LessEqual => return Ok(Value::Bool(l <= r)),
-                    BinaryOpTy::Greater => return Ok(Value::Bool(l > r)),
-                    BinaryOpTy::GreaterEqual => return Ok(Value::Bool(l >= r)),
-                    BinaryOpTy::Plus => return Ok(Value::Number(l + r)),
-                    BinaryOpTy::Minus => return Ok(Value::Number(l - r)),
-                    BinaryOpTy::Star => return Ok(Value::Number(l * r)),
-                    BinaryOpTy::Slash => return Ok(Value::Number(l / r)),
-                }
+    /// The value a `catch` clause binds for `error`, or `None` when it is not
+    /// an exception.
+    fn exception_value(&self, error: &Error) -> Option<Value> {
+        return exception_value(error, &self.runtime_error_class);
+    }
+
+    pub(crate) fn interpret_block(
+        &mut self,
+        statements: &[Stmt],
+        env: Rc<RefCell<Environment>>,
+    ) -> Result<(), Unwind> {
+        let previous = std::mem::replace(&mut self.env, env);
+        let mut res = Ok(());
+        for statement in statements {
+            res = self.evaluate(statement);
+            if res.is_err() {
+                break;
             }
         }
-        if let Value::String(ref l) = left {
-            if let Value::String(ref r) = right {
-                match op.ty {
-                    BinaryOpTy::Plus => {
-                        let mut s = l.to_owned();
-                        s.push_str(&r);
-                        return Ok(Value::String(s));
-                    }
-                    BinaryOpTy::EqualEqual => return Ok(Value::Bool(l == r)),
-                    BinaryOpTy::NotEqual => return Ok(Value::Bool(l != r)),
-                    _ => {
-                        let message = format!(
-                            "Wrong Binary Token In {op:?} with {right_expr:?} and {left_expr:?}"
-                        );
-                        return Err(Error::RunTimeException {
-                            message: String::from(message),
-                            line: op.line,
-                            col: op.col,
-                        });
-                    }
-                }
-            }
+        self.env = previous;
+        return res;
+    }
+}
+
+/// Rejects a call that passes the wrong number of arguments.
+pub fn check_arity(arity: Arity, got: usize, location: &SourceLocation) -> Result<(), Error> {
+    if arity.accepts(got) {
+        return Ok(());
+    }
+    let (expected, last) = match arity.max {
+        Some(max) if max == arity.min => (format!("{max}"), max),
+        Some(max) => (format!("{} to {max}", arity.min), max),
+        None => (format!("at least {}", arity.min), arity.min),
+    };
+    let noun = if last == 1 { "argument" } else { "arguments" };
+    return Err(Error::RunTimeException {
+        message: format!("Expected {expected} {noun} but got {got}."),
+        location: *location,
+    });
+}
+
+/// The arguments a `...value` spread passes to the call at `location`.
+pub fn spread(value: &Value, location: &SourceLocation) -> Result<Vec<Value>, Error> {
+    match value {
+        Value::List(list) => return Ok(list.borrow().clone()),
+        _ => {
+            return Err(Error::RunTimeException {
+                message: format!("Can only spread a list, got {value}."),
+                location: *location,
+            })
         }
-        if let Value::Bool(l) = left {
-            if let Value::Bool(r) = right {
-                match op.ty {
-                    BinaryOpTy::EqualEqual => return Ok(Value::Bool(l == r)),
-                    BinaryOpTy::NotEqual => return Ok(Value::Bool(l != r)),
-                    _ => {
-                        let message = format!(
-                            "Wrong Binary Token In {op:?} with {right_expr:?} and {left_expr:?}"
-                        );
-                        return Err(Error::RunTimeException {
-                            message: String::from(message),
-                            line: op.line,
-                            col: op.col,
-                        });
-                    }
+    }
+}
+
+/// The class of the instances that runtime errors become when caught.
+pub fn runtime_error_class() -> Rc<LoxClass> {
+    return Rc::new(LoxClass {
+        name: "RuntimeError".to_string(),
+        superclass: None,
+        methods: HashMap::new(),
+    });
+}
+
+/// The value a `catch` clause binds for `error`, or `None` for signals such
+/// as `return` and `break` that are not exceptions. Runtime errors become
+/// instances of `runtime_error_class`.
+pub fn exception_value(error: &Error, runtime_error_class: &Rc<LoxClass>) -> Option<Value> {
+    let (message, line, col) = match error {
+        Error::Traced { error, trace } => {
+            let value = exception_value(error, runtime_error_class)?;
+            if let Value::Instance(instance) = &value {
+                if Rc::ptr_eq(&instance.borrow().class, runtime_error_class) {
+                    let trace = trace
+                        .iter()
+                        .map(|frame| Value::String(frame.to_string()))
+                        .collect();
+                    instance.borrow_mut().fields.insert(
+                        "trace".to_string(),
+                        Value::List(Rc::new(RefCell::new(trace))),
+                    );
                 }
             }
+            return Some(value);
         }
-        if let Value::Nil = left {
-            if let Value::Nil = right {
-                match op.ty {
-                    BinaryOpTy::EqualEqual => return Ok(Value::Bool(true)),
-                    BinaryOpTy::NotEqual => return Ok(Value::Bool(false)),
-                    _ => {
-                        let message = format!(
-                            "Wrong Binary Token In {op:?} with {right_expr:?} and {left_expr:?}"
-                        );
-                        return Err(Error::RunTimeException {
-                            message: String::from(message),
-                            line: op.line,
-                            col: op.col,
-                        });
-                    }
-                }
+        Error::Throw { value, .. } => return Some(value.clone()),
+        Error::RunTimeException { message, location } => {
+            (message.clone(), location.line, location.col)
+        }
+        Error::UndefinedVariable { name, location } => (
+            format!("Undefined variable `{name}`"),
+            location.line,
+            location.col,
+        ),
+        Error::JustError { message } => (message.clone(), 0, -1),
+        _ => return None,
+    };
+    let mut fields = HashMap::new();
+    fields.insert("message".to_string(), Value::String(message));
+    fields.insert("line".to_string(), Value::Number(line as f64));
+    fields.insert("col".to_string(), Value::Number(col as f64));
+    return Some(Value::Instance(Rc::new(RefCell::new(LoxInstance {
+        class: runtime_error_class.clone(),
+        fields,
+    }))));
+}
+
+pub fn unary_op(op: &UnaryOp, right: Value) -> Result<Value, Error> {
+    match op.ty {
+        UnaryOpTy::Minus => {
+            if let Value::Number(v) = right {
+                return Ok(Value::Number(-v));
             }
+            let message = format!("Cannot negate {right}.");
+            return Err(Error::RunTimeException {
+                message,
+                location: op.location(),
+            });
+        }
+        UnaryOpTy::Bang => {
+            let location = op.location();
+            return Ok(Value::Bool(!is_truthy(&right, &location)?));
         }
-        let message = format!("Wrong Binary Token In {op:?} with {right_expr:?} and {left_expr:?}");
-        return Err(Error::RunTimeException {
-            message: String::from(message),
-            line: op.line,
-            col: op.col,
-        });
     }
+}
 
-    pub fn interpret_block(&mut self, statements: &[Stmt], env: Environment) -> Result<(), Error> {
-        self.env = env;
-        for statement in statements {
-            if let Stmt::Break = statement {
-                self.loop_stack.pop();
-                break;
+pub fn binary_op(op: &BinaryOp, left: Value, right: Value) -> Result<Value, Error> {
+    match op.ty {
+        BinaryOpTy::EqualEqual => return Ok(Value::Bool(values_equal(&left, &right))),
+        BinaryOpTy::NotEqual => return Ok(Value::Bool(!values_equal(&left, &right))),
+        _ => {}
+    }
+    match (&left, &right) {
+        (Value::Number(l), Value::Number(r)) => {
+            let (l, r) = (*l, *r);
+            match op.ty {
+                BinaryOpTy::EqualEqual | BinaryOpTy::NotEqual => unreachable!(),
+                BinaryOpTy::Less => return Ok(Value::Bool(l < r)),
+                BinaryOpTy::LessEqual => return Ok(Value::Bool(l <= r)),
+                BinaryOpTy::Greater => return Ok(Value::Bool(l > r)),
+                BinaryOpTy::GreaterEqual => return Ok(Value::Bool(l >= r)),
+                BinaryOpTy::Plus => return Ok(Value::Number(l + r)),
+                BinaryOpTy::Minus => return Ok(Value::Number(l - r)),
+                BinaryOpTy::Star => return Ok(Value::Number(l * r)),
+                BinaryOpTy::Slash => return Ok(Value::Number(l / r)),
             }
-            let res = self.evaluate(statement);
-            if let Err(e) = res {
-                return Err(e);
+        }
+        (Value::String(l), Value::String(r)) if matches!(op.ty, BinaryOpTy::Plus) => {
+            let mut s = l.to_owned();
+            s.push_str(r);
+            return Ok(Value::String(s));
+        }
+        _ => {}
+    }
+    let message = format!("Cannot apply `{}` to {left} and {right}.", op.ty);
+    return Err(Error::RunTimeException {
+        message,
+        location: op.location(),
+    });
+}
+
+/// Whether `==` holds between two values. Numbers, strings, booleans and
+/// `nil` compare by value; lists, maps, instances, classes and functions only
+/// equal themselves. Values of different types are never equal.
+fn values_equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Number(l), Value::Number(r)) => return l == r,
+        (Value::String(l), Value::String(r)) => return l == r,
+        (Value::Bool(l), Value::Bool(r)) => return l == r,
+        (Value::Nil, Value::Nil) => return true,
+        (Value::List(l), Value::List(r)) => return Rc::ptr_eq(l, r),
+        (Value::Map(l), Value::Map(r)) => return Rc::ptr_eq(l, r),
+        (Value::Instance(l), Value::Instance(r)) => return Rc::ptr_eq(l, r),
+        (Value::Class(l), Value::Class(r)) => return Rc::ptr_eq(l, r),
+        (Value::Closure(l), Value::Closure(r)) => return Rc::ptr_eq(l, r),
+        (Value::NativeFunction(l), Value::NativeFunction(r)) => {
+            return Rc::ptr_eq(&l.callable, &r.callable);
+        }
+        // Copies of a function share its environment and declaration.
+        (Value::LoxFunction(l), Value::LoxFunction(r)) => {
+            return Rc::ptr_eq(&l.closure, &r.closure)
+                && Rc::ptr_eq(&l.declaration, &r.declaration);
+        }
+        _ => return false,
+    }
+}
+
+/// Reads `value[slice]` for lists (by index) and maps (by key).
+pub fn get_item(value: Value, slice: Value, location: &SourceLocation) -> Result<Value, Error> {
+    match value {
+        Value::List(list) => {
+            let list = list.borrow();
+            let index = list_index(&slice, list.len(), location)?;
+            return Ok(list[index].clone());
+        }
+        Value::Map(map) => {
+            let key = map_key(&slice, location)?;
+            match map.borrow().get(&key) {
+                Some(value) => return Ok(value.clone()),
+                None => {
+                    return Err(Error::RunTimeException {
+                        message: format!("Key {slice} not found in map."),
+                        location: *location,
+                    })
+                }
             }
         }
-        if let Some(enclosing) = self.env.enclosing.clone() {
-            self.env = *enclosing;
+        _ => {
+            return Err(Error::RunTimeException {
+                message: format!("Can't subscript value {value}."),
+                location: *location,
+            })
         }
-        return Ok(());
     }
 }
 
-fn is_truthy(val: &Value) -> Result<bool, Error> {
+/// Stores `rhs` into `lhs[slice]` and returns it, like any assignment.
+pub fn set_item(
+    lhs: Value,
+    slice: Value,
+    rhs: Value,
+    location: &SourceLocation,
+) -> Result<Value, Error> {
+    match lhs {
+        Value::List(list) => {
+            let mut list = list.borrow_mut();
+            let index = list_index(&slice, list.len(), location)?;
+            list[index] = rhs.clone();
+            return Ok(rhs);
+        }
+        Value::Map(map) => {
+            let key = map_key(&slice, location)?;
+            map.borrow_mut().insert(key, rhs.clone());
+            return Ok(rhs);
+        }
+        _ => {
+            return Err(Error::RunTimeException {
+                message: format!("Can't assign to an item of value {lhs}."),
+                location: *location,
+            })
+        }
+    }
+}
+
+/// Turns a subscript into a position in a list of `len` items. Negative
+/// indices count from the end, so `xs[-1]` is the last item.
+fn list_index(slice: &Value, len: usize, location: &SourceLocation) -> Result<usize, Error> {
+    let index = match slice {
+        Value::Number(n) if n.fract() == 0.0 => *n as i64,
+        _ => {
+            return Err(Error::RunTimeException {
+                message: format!("List index must be an integer, got {slice}."),
+                location: *location,
+            })
+        }
+    };
+    let len = len as i64;
+    let resolved = if index < 0 { index + len } else { index };
+    if resolved < 0 || resolved >= len {
+        return Err(Error::RunTimeException {
+            message: format!("List index {index} out of range for list of length {len}."),
+            location: *location,
+        });
+    }
+    return Ok(resolved as usize);
+}
+
+pub fn map_key(key: &Value, location: &SourceLocation) -> Result<MapKey, Error> {
+    return MapKey::try_from(key).map_err(|message| Error::RunTimeException {
+        message,
+        location: *location,
+    });
+}
+
+pub fn is_truthy(val: &Value, location: &SourceLocation) -> Result<bool, Error> {
     match val {
         Value::Number(v) => {
             if *v > 0.0 {
@@ -365,8 +1010,9 @@ fn is_truthy(val: &Value) -> Result<bool, Error> {
             return Ok(*v);
         }
         _ => {
-            return Err(Error::JustError {
-                message: String::from("{val} cannot be casted to bool"),
+            return Err(Error::RunTimeException {
+                message: format!("{val} cannot be casted to bool"),
+                location: *location,
             })
         }
     }
@@ -0,0 +1,198 @@
+#![allow(clippy::needless_return)]
+
+//! A Lox interpreter that can be embedded in other programs.
+//!
+//! ```
+//! use rust_interpreter::{Lox, Value};
+//!
+//! let mut lox = Lox::new();
+//! lox.eval("fun add(a, b) { return a + b; }").unwrap();
+//! let sum = lox
+//!     .call_function("add", &[Value::Number(1.0), Value::Number(2.0)])
+//!     .unwrap();
+//! assert_eq!(sum.to_string(), "3");
+//! ```
+
+pub mod ast;
+pub mod bytecode;
+pub mod compiler;
+pub mod debug;
+pub mod diagnostic;
+pub mod env;
+pub mod error;
+pub mod expr;
+pub mod formatter;
+pub mod interpreter;
+pub mod loxc;
+pub mod natives;
+pub mod output;
+pub mod parser;
+pub mod repl;
+pub mod resolver;
+pub mod scanner;
+pub mod vm;
+
+use std::fmt;
+
+pub use error::Error;
+pub use expr::{NativeFunction, Value};
+pub use interpreter::{Interpreter, InterpreterBuilder};
+pub use natives::NativeContext;
+pub use output::Output;
+
+use diagnostic::Diagnostic;
+use expr::{FileId, SourceLocation};
+use parser::LoxParser;
+
+/// Anything that can go wrong while evaluating source with [`Lox`].
+pub enum LoxError {
+    /// The source contains an invalid token.
+    Scan(scanner::Error),
+    /// The source is not valid Lox syntax. Holds every syntax error found.
+    Parse(Vec<Error>),
+    /// The program is well formed but breaks a static rule, such as reading a
+    /// local variable in its own initializer.
+    Resolve(Vec<Error>),
+    /// The program raised an error that nothing caught.
+    Runtime(Error),
+    /// The program called `exit` with this status. Not a failure in itself;
+    /// the host decides what the status means.
+    Exit(u8),
+}
+
+impl fmt::Debug for LoxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoxError::Scan(e) => write!(f, "{e:?}"),
+            LoxError::Runtime(e) => write!(f, "{e:?}"),
+            LoxError::Exit(code) => write!(f, "Exit({code})"),
+            LoxError::Parse(errors) | LoxError::Resolve(errors) => {
+                for (i, e) in errors.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{e:?}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for LoxError {
+    /// One line per error, such as ``[line 2:11] Undefined variable `y` ``
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let LoxError::Exit(code) = self {
+            return write!(f, "The script called exit({code}).");
+        }
+        for (i, diagnostic) in self.diagnostics().iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            match (diagnostic.line, diagnostic.col) {
+                (0, _) => write!(f, "{}", diagnostic.message)?,
+                (line, col) if col > 0 => write!(f, "[line {line}:{col}] {}", diagnostic.message)?,
+                (line, _) => write!(f, "[line {line}] {}", diagnostic.message)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for LoxError {}
+
+/// Wraps an error that stopped a running script, telling an `exit` call
+/// apart from a failure.
+fn runtime_error(error: Error) -> LoxError {
+    match error {
+        Error::Exit { code } => return LoxError::Exit(code),
+        error => return LoxError::Runtime(error),
+    }
+}
+
+impl LoxError {
+    /// One report per underlying error, ready to render against the source
+    /// that was evaluated.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        match self {
+            LoxError::Scan(e) => return vec![Diagnostic::from(e)],
+            LoxError::Runtime(e) => return vec![Diagnostic::from(e)],
+            LoxError::Exit(_) => return Vec::new(),
+            LoxError::Parse(errors) | LoxError::Resolve(errors) => {
+                return errors.iter().map(Diagnostic::from).collect()
+            }
+        }
+    }
+}
+
+/// An interpreter session. Globals defined by one `eval` stay visible to the
+/// next, so a host can load a script once and then call into it. Nothing is
+/// written to stderr and the process is never exited; errors are returned.
+pub struct Lox {
+    interpreter: Interpreter,
+    /// Id given to the source of the next `eval`, so spans from different
+    /// calls never compare equal.
+    next_file: u32,
+}
+
+impl Default for Lox {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl From<Interpreter> for Lox {
+    /// Wraps an interpreter, e.g. one made with [`Interpreter::builder`].
+    fn from(interpreter: Interpreter) -> Self {
+        return Self {
+            interpreter,
+            next_file: 0,
+        };
+    }
+}
+
+impl Lox {
+    pub fn new() -> Self {
+        return Self::from(Interpreter::default());
+    }
+
+    /// Runs `source` and returns the value of its last statement when that
+    /// is an expression statement, or `nil` otherwise.
+    pub fn eval(&mut self, source: &str) -> Result<Value, LoxError> {
+        let file = FileId(self.next_file);
+        self.next_file += 1;
+        let tokens = scanner::scan_tokens(source.to_string(), file).map_err(LoxError::Scan)?;
+        let (stmts, errors) = LoxParser::from_tokens(tokens).parse();
+        if !errors.is_empty() {
+            return Err(LoxError::Parse(errors));
+        }
+        let locals = resolver::resolve(&stmts).map_err(LoxError::Resolve)?;
+        self.interpreter.resolve(locals);
+        return self.interpreter.interpret(&stmts).map_err(runtime_error);
+    }
+
+    /// Where `print` writes; see [`Output`].
+    pub fn output(&mut self) -> &mut Output {
+        return &mut self.interpreter.output;
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        return self.interpreter.get_global(name);
+    }
+
+    /// Defines or overwrites the global `name`.
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.interpreter.set_global(name, value);
+    }
+
+    /// Calls the global function or class `name` with `args`.
+    pub fn call_function(&mut self, name: &str, args: &[Value]) -> Result<Value, LoxError> {
+        let callee = self.get_global(name).ok_or_else(|| {
+            LoxError::Runtime(Error::UndefinedVariable {
+                name: name.to_string(),
+                location: SourceLocation::none(),
+            })
+        })?;
+        return self.interpreter.call(callee, args).map_err(runtime_error);
+    }
+}
@@ -0,0 +1,593 @@
+//! The `.loxc` format: a compiled script saved to disk so it can run without
+//! being scanned, parsed and compiled again.
+//!
+//! A file starts with a header of the magic bytes `LOXC`, a format version
+//! (`u16`), the payload length (`u32`) and a CRC-32 of the payload (`u32`).
+//! The payload is the script's `FunctionProto`: name, arity, number of
+//! optional parameters, rest flag, upvalues, code, one source location per
+//! instruction (line, column, file id and byte span) and the constant pool,
+//! with nested functions stored inline in the pool. All integers are
+//! little-endian.
+
+use std::rc::Rc;
+
+use crate::{
+    bytecode::{Chunk, Constant, FunctionProto, OpCode, UpvalueDesc},
+    error::Error,
+    expr::{FileId, SourceLocation, Span},
+};
+
+pub const MAGIC: &[u8; 4] = b"LOXC";
+pub const VERSION: u16 = 3;
+const HEADER_LEN: usize = 14;
+
+/// Whether `bytes` look like a compiled script rather than source code.
+pub fn is_loxc(bytes: &[u8]) -> bool {
+    return bytes.starts_with(MAGIC);
+}
+
+pub fn save(function: &FunctionProto) -> Vec<u8> {
+    let mut payload = Writer::default();
+    payload.function(function);
+    let payload = payload.bytes;
+
+    let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&crc32(&payload).to_le_bytes());
+    bytes.extend_from_slice(&payload);
+    return bytes;
+}
+
+pub fn load(bytes: &[u8]) -> Result<Rc<FunctionProto>, Error> {
+    if !is_loxc(bytes) {
+        return Err(invalid("not a compiled Lox script (bad magic header)"));
+    }
+    if bytes.len() < HEADER_LEN {
+        return Err(invalid("file is truncated (incomplete header)"));
+    }
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != VERSION {
+        return Err(invalid(&format!(
+            "unsupported format version {version}, expected {VERSION}; recompile the script"
+        )));
+    }
+    let len = u32::from_le_bytes(bytes[6..10].try_into().unwrap()) as usize;
+    let checksum = u32::from_le_bytes(bytes[10..14].try_into().unwrap());
+    let payload = &bytes[HEADER_LEN..];
+    if payload.len() < len {
+        return Err(invalid(&format!(
+            "file is truncated (expected {len} bytes of code, found {})",
+            payload.len()
+        )));
+    }
+    if payload.len() > len {
+        return Err(invalid("unexpected data after the end of the script"));
+    }
+    if crc32(payload) != checksum {
+        return Err(invalid("checksum mismatch, the file is corrupted"));
+    }
+
+    let mut reader = Reader {
+        bytes: payload,
+        pos: 0,
+    };
+    let function = reader.function()?;
+    if reader.pos != payload.len() {
+        return Err(invalid("unexpected data after the end of the script"));
+    }
+    return Ok(Rc::new(function));
+}
+
+fn invalid(message: &str) -> Error {
+    return Error::InvalidLoxc {
+        message: message.to_string(),
+    };
+}
+
+/// CRC-32 (IEEE 802.3), computed bit by bit.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    return !crc;
+}
+
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u32(&mut self, value: usize) {
+        self.bytes.extend_from_slice(&(value as u32).to_le_bytes());
+    }
+
+    fn i64(&mut self, value: i64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn f64(&mut self, value: f64) {
+        self.bytes.extend_from_slice(&value.to_bits().to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u32(value.len());
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    fn function(&mut self, function: &FunctionProto) {
+        self.string(&function.name);
+        self.u32(function.arity);
+        self.u32(function.optional);
+        self.u8(function.rest as u8);
+        self.u32(function.upvalues.len());
+        for upvalue in &function.upvalues {
+            self.u8(upvalue.is_local as u8);
+            self.u32(upvalue.index);
+        }
+        self.chunk(&function.chunk);
+    }
+
+    fn chunk(&mut self, chunk: &Chunk) {
+        self.u32(chunk.code.len());
+        for op in &chunk.code {
+            self.op(op);
+        }
+        for location in &chunk.locations {
+            self.u32(location.line);
+            self.i64(location.col);
+            self.u32(location.span.file.0 as usize);
+            self.u32(location.span.start);
+            self.u32(location.span.end);
+        }
+        self.u32(chunk.constants.len());
+        for constant in &chunk.constants {
+            match constant {
+                Constant::Number(n) => {
+                    self.u8(0);
+                    self.f64(*n);
+                }
+                Constant::String(s) => {
+                    self.u8(1);
+                    self.string(s);
+                }
+                Constant::Function(function) => {
+                    self.u8(2);
+                    self.function(function);
+                }
+            }
+        }
+    }
+
+    fn op(&mut self, op: &OpCode) {
+        let (tag, operands): (u8, &[usize]) = match op {
+            OpCode::Constant(i) => (0, &[*i]),
+            OpCode::Nil => (1, &[]),
+            OpCode::True => (2, &[]),
+            OpCode::False => (3, &[]),
+            OpCode::Undefined => (4, &[]),
+            OpCode::Pop => (5, &[]),
+            OpCode::GetLocal { slot, name } => (6, &[*slot, *name]),
+            OpCode::SetLocal(i) => (7, &[*i]),
+            OpCode::GetGlobal(i) => (8, &[*i]),
+            OpCode::DefineGlobal(i) => (9, &[*i]),
+            OpCode::SetGlobal(i) => (10, &[*i]),
+            OpCode::GetUpvalue { index, name } => (11, &[*index, *name]),
+            OpCode::SetUpvalue(i) => (12, &[*i]),
+            OpCode::GetProperty(i) => (13, &[*i]),
+            OpCode::SetProperty(i) => (14, &[*i]),
+            OpCode::GetSuper(i) => (15, &[*i]),
+            OpCode::GetIndex => (16, &[]),
+            OpCode::SetIndex => (17, &[]),
+            OpCode::Equal => (18, &[]),
+            OpCode::NotEqual => (19, &[]),
+            OpCode::Greater => (20, &[]),
+            OpCode::GreaterEqual => (21, &[]),
+            OpCode::Less => (22, &[]),
+            OpCode::LessEqual => (23, &[]),
+            OpCode::Add => (24, &[]),
+            OpCode::Subtract => (25, &[]),
+            OpCode::Multiply => (26, &[]),
+            OpCode::Divide => (27, &[]),
+            OpCode::Not => (28, &[]),
+            OpCode::Negate => (29, &[]),
+            OpCode::Print => (30, &[]),
+            OpCode::Jump(i) => (31, &[*i]),
+            OpCode::JumpIfFalse(i) => (32, &[*i]),
+            OpCode::Call(i) => (33, &[*i]),
+            OpCode::Closure(i) => (34, &[*i]),
+            OpCode::CloseUpvalue => (35, &[]),
+            OpCode::Return => (36, &[]),
+            OpCode::Class {
+                name,
+                methods,
+                superclass,
+            } => (37, &[*name, *methods, *superclass as usize]),
+            OpCode::List(i) => (38, &[*i]),
+            OpCode::Map(i) => (39, &[*i]),
+            OpCode::Throw => (40, &[]),
+            OpCode::PushHandler(i) => (41, &[*i]),
+            OpCode::PopHandler => (42, &[]),
+            OpCode::PopException => (43, &[]),
+            OpCode::Rethrow => (44, &[]),
+            OpCode::CallSpread(i) => (45, &[*i]),
+            OpCode::DefaultArg { slot, target } => (46, &[*slot, *target]),
+        };
+        self.u8(tag);
+        for operand in operands {
+            self.u32(*operand);
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], Error> {
+        if self.bytes.len() - self.pos < len {
+            return Err(invalid("file is truncated (unexpected end of data)"));
+        }
+        let slice = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        return Ok(slice);
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        return Ok(self.take(1)?[0]);
+    }
+
+    fn u32(&mut self) -> Result<usize, Error> {
+        let bytes = self.take(4)?;
+        return Ok(u32::from_le_bytes(bytes.try_into().unwrap()) as usize);
+    }
+
+    fn i64(&mut self) -> Result<i64, Error> {
+        let bytes = self.take(8)?;
+        return Ok(i64::from_le_bytes(bytes.try_into().unwrap()));
+    }
+
+    fn f64(&mut self) -> Result<f64, Error> {
+        let bytes = self.take(8)?;
+        return Ok(f64::from_bits(u64::from_le_bytes(
+            bytes.try_into().unwrap(),
+        )));
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        let len = self.u32()?;
+        let bytes = self.take(len)?.to_vec();
+        return String::from_utf8(bytes).map_err(|_| invalid("string constant is not UTF-8"));
+    }
+
+    fn function(&mut self) -> Result<FunctionProto, Error> {
+        let name = self.string()?;
+        let arity = self.u32()?;
+        let optional = self.u32()?;
+        let rest = self.u8()? != 0;
+        let upvalue_count = self.u32()?;
+        let mut upvalues = Vec::new();
+        for _ in 0..upvalue_count {
+            let is_local = self.u8()? != 0;
+            let index = self.u32()?;
+            upvalues.push(UpvalueDesc { is_local, index });
+        }
+        let chunk = self.chunk()?;
+        let function = FunctionProto {
+            name,
+            arity,
+            optional,
+            rest,
+            upvalues,
+            chunk,
+        };
+        validate(&function)?;
+        return Ok(function);
+    }
+
+    fn chunk(&mut self) -> Result<Chunk, Error> {
+        let code_len = self.u32()?;
+        let mut code = Vec::new();
+        for _ in 0..code_len {
+            code.push(self.op()?);
+        }
+        let mut locations = Vec::new();
+        for _ in 0..code_len {
+            let line = self.u32()?;
+            let col = self.i64()?;
+            let span = Span {
+                file: FileId(self.u32()? as u32),
+                start: self.u32()?,
+                end: self.u32()?,
+            };
+            locations.push(SourceLocation { line, col, span });
+        }
+        let constant_count = self.u32()?;
+        let mut constants = Vec::new();
+        for _ in 0..constant_count {
+            let constant = match self.u8()? {
+                0 => Constant::Number(self.f64()?),
+                1 => Constant::String(self.string()?),
+                2 => Constant::Function(Rc::new(self.function()?)),
+                tag => return Err(invalid(&format!("unknown constant tag {tag}"))),
+            };
+            constants.push(constant);
+        }
+        return Ok(Chunk {
+            code,
+            locations,
+            constants,
+        });
+    }
+
+    fn op(&mut self) -> Result<OpCode, Error> {
+        let op = match self.u8()? {
+            0 => OpCode::Constant(self.u32()?),
+            1 => OpCode::Nil,
+            2 => OpCode::True,
+            3 => OpCode::False,
+            4 => OpCode::Undefined,
+            5 => OpCode::Pop,
+            6 => OpCode::GetLocal {
+                slot: self.u32()?,
+                name: self.u32()?,
+            },
+            7 => OpCode::SetLocal(self.u32()?),
+            8 => OpCode::GetGlobal(self.u32()?),
+            9 => OpCode::DefineGlobal(self.u32()?),
+            10 => OpCode::SetGlobal(self.u32()?),
+            11 => OpCode::GetUpvalue {
+                index: self.u32()?,
+                name: self.u32()?,
+            },
+            12 => OpCode::SetUpvalue(self.u32()?),
+            13 => OpCode::GetProperty(self.u32()?),
+            14 => OpCode::SetProperty(self.u32()?),
+            15 => OpCode::GetSuper(self.u32()?),
+            16 => OpCode::GetIndex,
+            17 => OpCode::SetIndex,
+            18 => OpCode::Equal,
+            19 => OpCode::NotEqual,
+            20 => OpCode::Greater,
+            21 => OpCode::GreaterEqual,
+            22 => OpCode::Less,
+            23 => OpCode::LessEqual,
+            24 => OpCode::Add,
+            25 => OpCode::Subtract,
+            26 => OpCode::Multiply,
+            27 => OpCode::Divide,
+            28 => OpCode::Not,
+            29 => OpCode::Negate,
+            30 => OpCode::Print,
+            31 => OpCode::Jump(self.u32()?),
+            32 => OpCode::JumpIfFalse(self.u32()?),
+            33 => OpCode::Call(self.u32()?),
+            34 => OpCode::Closure(self.u32()?),
+            35 => OpCode::CloseUpvalue,
+            36 => OpCode::Return,
+            37 => OpCode::Class {
+                name: self.u32()?,
+                methods: self.u32()?,
+                superclass: self.u32()? != 0,
+            },
+            38 => OpCode::List(self.u32()?),
+            39 => OpCode::Map(self.u32()?),
+            40 => OpCode::Throw,
+            41 => OpCode::PushHandler(self.u32()?),
+            42 => OpCode::PopHandler,
+            43 => OpCode::PopException,
+            44 => OpCode::Rethrow,
+            45 => OpCode::CallSpread(self.u32()?),
+            46 => OpCode::DefaultArg {
+                slot: self.u32()?,
+                target: self.u32()?,
+            },
+            tag => return Err(invalid(&format!("unknown opcode {tag}"))),
+        };
+        return Ok(op);
+    }
+}
+
+/// Checks a loaded function the way the compiler would have built it, so a
+/// damaged file fails here instead of inside the VM: operands must refer to
+/// constants of the right kind, to upvalues that exist and to offsets inside
+/// the chunk, and every instruction must find the stack slots it reads.
+fn validate(function: &FunctionProto) -> Result<(), Error> {
+    let chunk = &function.chunk;
+    if function.optional > function.arity {
+        return Err(invalid(
+            "function has more optional parameters than parameters",
+        ));
+    }
+    let is_string = |index: usize| matches!(chunk.constants.get(index), Some(Constant::String(_)));
+    let functions = chunk
+        .constants
+        .iter()
+        .filter(|constant| matches!(constant, Constant::Function(_)))
+        .count();
+    for op in &chunk.code {
+        let ok = match *op {
+            OpCode::Constant(i) => {
+                matches!(
+                    chunk.constants.get(i),
+                    Some(Constant::Number(_) | Constant::String(_))
+                )
+            }
+            OpCode::Closure(i) => matches!(chunk.constants.get(i), Some(Constant::Function(_))),
+            OpCode::GetUpvalue { index, name } => {
+                index < function.upvalues.len() && is_string(name)
+            }
+            OpCode::SetUpvalue(index) => index < function.upvalues.len(),
+            OpCode::Class { name, methods, .. } => is_string(name) && methods <= functions,
+            OpCode::GetLocal { name, .. }
+            | OpCode::GetGlobal(name)
+            | OpCode::DefineGlobal(name)
+            | OpCode::SetGlobal(name)
+            | OpCode::GetProperty(name)
+            | OpCode::SetProperty(name)
+            | OpCode::GetSuper(name) => is_string(name),
+            OpCode::Jump(target)
+            | OpCode::JumpIfFalse(target)
+            | OpCode::PushHandler(target)
+            | OpCode::DefaultArg { target, .. } => target < chunk.code.len(),
+            _ => true,
+        };
+        if !ok {
+            return Err(invalid(&format!("malformed instruction {op:?}")));
+        }
+    }
+    if !matches!(chunk.code.last(), Some(OpCode::Return)) {
+        return Err(invalid("function does not end with a return"));
+    }
+    return check_stack(function);
+}
+
+/// What the VM holds for a function at one instruction: the number of stack
+/// slots above the frame's base, and the number of caught exceptions waiting
+/// to be dropped or raised again.
+#[derive(Clone, Copy, PartialEq)]
+struct StackState {
+    height: usize,
+    exceptions: usize,
+}
+
+/// Follows every path through `function`, checking that each instruction
+/// reaches the same stack height on all of them and never reads below the
+/// frame.
+fn check_stack(function: &FunctionProto) -> Result<(), Error> {
+    let code = &function.chunk.code;
+    let mut states: Vec<Option<StackState>> = vec![None; code.len()];
+    // The callee or receiver, the parameters and the rest list.
+    let entry = StackState {
+        height: 1 + function.arity + function.rest as usize,
+        exceptions: 0,
+    };
+    let mut pending = vec![(0, entry)];
+    while let Some((ip, state)) = pending.pop() {
+        match states[ip] {
+            Some(seen) if seen == state => continue,
+            Some(_) => return Err(invalid(&format!("inconsistent stack at offset {ip}"))),
+            None => states[ip] = Some(state),
+        }
+        let op = code[ip];
+        let malformed = || invalid(&format!("malformed instruction {op:?} at offset {ip}"));
+        let StackState { height, exceptions } = state;
+        let (pops, pushes) = match op {
+            OpCode::Constant(_)
+            | OpCode::Nil
+            | OpCode::True
+            | OpCode::False
+            | OpCode::Undefined
+            | OpCode::GetGlobal(_)
+            | OpCode::GetUpvalue { .. } => (0, 1),
+            // A closure captures locals of this frame and upvalues of this
+            // function.
+            OpCode::Closure(index) => {
+                let Constant::Function(inner) = &function.chunk.constants[index] else {
+                    return Err(malformed());
+                };
+                let captures_exist = inner.upvalues.iter().all(|upvalue| match upvalue.is_local {
+                    true => upvalue.index < height,
+                    false => upvalue.index < function.upvalues.len(),
+                });
+                if !captures_exist {
+                    return Err(malformed());
+                }
+                (0, 1)
+            }
+            OpCode::GetLocal { slot, .. } if slot < height => (0, 1),
+            OpCode::SetLocal(slot) if slot < height => (1, 1),
+            OpCode::DefaultArg { slot, .. } if slot < height => (0, 0),
+            OpCode::GetLocal { .. } | OpCode::SetLocal(_) | OpCode::DefaultArg { .. } => {
+                return Err(malformed());
+            }
+            OpCode::Pop | OpCode::DefineGlobal(_) | OpCode::Print | OpCode::CloseUpvalue => (1, 0),
+            OpCode::SetGlobal(_)
+            | OpCode::SetUpvalue(_)
+            | OpCode::GetProperty(_)
+            | OpCode::Not
+            | OpCode::Negate
+            | OpCode::JumpIfFalse(_) => (1, 1),
+            OpCode::SetProperty(_)
+            | OpCode::GetSuper(_)
+            | OpCode::GetIndex
+            | OpCode::Equal
+            | OpCode::NotEqual
+            | OpCode::Greater
+            | OpCode::GreaterEqual
+            | OpCode::Less
+            | OpCode::LessEqual
+            | OpCode::Add
+            | OpCode::Subtract
+            | OpCode::Multiply
+            | OpCode::Divide => (2, 1),
+            OpCode::SetIndex => (3, 1),
+            OpCode::Call(count) | OpCode::CallSpread(count) => (count + 1, 1),
+            OpCode::Class {
+                methods,
+                superclass,
+                ..
+            } => (methods + superclass as usize, 1 + superclass as usize),
+            OpCode::List(count) => (count, 1),
+            OpCode::Map(count) => (2 * count, 1),
+            OpCode::Jump(_)
+            | OpCode::PushHandler(_)
+            | OpCode::PopHandler
+            | OpCode::PopException => (0, 0),
+            OpCode::Return | OpCode::Throw => (1, 0),
+            OpCode::Rethrow => (0, 0),
+        };
+        if pops > height {
+            return Err(malformed());
+        }
+        let exceptions = match op {
+            OpCode::PopException | OpCode::Rethrow if exceptions == 0 => return Err(malformed()),
+            OpCode::PopException => exceptions - 1,
+            _ => exceptions,
+        };
+        let next = StackState {
+            height: height - pops + pushes,
+            exceptions,
+        };
+        let mut successors = Vec::new();
+        match op {
+            OpCode::Return | OpCode::Throw | OpCode::Rethrow => {}
+            OpCode::Jump(target) => successors.push((target, next)),
+            OpCode::JumpIfFalse(target) | OpCode::DefaultArg { target, .. } => {
+                successors.push((target, next));
+                successors.push((ip + 1, next));
+            }
+            // The handler resumes with the stack as it is now plus the
+            // caught value.
+            OpCode::PushHandler(target) => {
+                let caught = StackState {
+                    height: height + 1,
+                    exceptions: exceptions + 1,
+                };
+                successors.push((target, caught));
+                successors.push((ip + 1, next));
+            }
+            _ => successors.push((ip + 1, next)),
+        }
+        for (target, state) in successors {
+            if target >= code.len() {
+                return Err(invalid("code runs past the end of the function"));
+            }
+            pending.push((target, state));
+        }
+    }
+    return Ok(());
+}
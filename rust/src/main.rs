@@ -1,78 +1,615 @@
-mod env;
-mod error;
-mod expr;
-mod interpreter;
-mod parser;
-mod scanner;
+#![allow(clippy::needless_return)]
 
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::io::{Read, Write};
+use std::process::ExitCode;
+use std::rc::Rc;
+use std::time::Instant;
 
-use clap::Parser;
-use env::Environment;
-use parser::LoxParser;
-use scanner::{scan_tokens, Token};
-
-use crate::interpreter::Interpreter;
+use clap::{Parser, Subcommand};
+use rust_interpreter::bytecode::FunctionProto;
+use rust_interpreter::diagnostic::{self, Diagnostic};
+use rust_interpreter::env::Environment;
+use rust_interpreter::expr::{FileId, Stmt};
+use rust_interpreter::natives::NativeContext;
+use rust_interpreter::parser::LoxParser;
+use rust_interpreter::repl::{Line, LineBuffer};
+use rust_interpreter::resolver::Locals;
+use rust_interpreter::scanner::{scan_tokens, scan_tokens_with_comments, Token};
+use rust_interpreter::vm::Vm;
+use rust_interpreter::{
+    ast, compiler, debug, error, formatter, loxc, repl, resolver, Interpreter, Value,
+};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Arguments {
-    #[arg(short, long)]
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Deprecated spelling of `run <FILE>`, kept so existing invocations
+    /// still work. Takes no script arguments.
+    #[arg(short, long, hide = true)]
     file: Option<String>,
+    /// Run <CODE> instead of a script.
+    #[arg(short, long, value_name = "CODE", conflicts_with = "file")]
+    eval: Option<String>,
+    /// Compile to bytecode and run on the VM instead of walking the tree.
+    #[arg(long, global = true)]
+    vm: bool,
+    /// Print the compiled bytecode before running it. Implies `--vm`.
+    #[arg(long, global = true)]
+    disassemble: bool,
+    /// Print the VM stack and each instruction as it executes. Implies `--vm`.
+    #[arg(long, global = true)]
+    trace: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run a script or a compiled `.loxc` file.
+    Run {
+        /// The script, or `-` to read it from stdin.
+        file: String,
+        /// Arguments for the script.
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Scan, parse and resolve a script without running it.
+    Check {
+        /// The script, or `-` to read it from stdin.
+        file: String,
+    },
+    /// Print the tokens a script scans to.
+    Tokens {
+        /// The script, or `-` to read it from stdin.
+        file: String,
+    },
+    /// Print the statements a script parses to.
+    Ast {
+        /// The script, or `-` to read it from stdin.
+        file: String,
+        /// Print JSON instead of an indented tree.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print a script reformatted, comments included.
+    Fmt {
+        /// The script, or `-` to read it from stdin.
+        file: String,
+        /// Rewrite the file in place instead of printing it.
+        #[arg(short, long)]
+        write: bool,
+    },
+    /// Compile a script to a `.loxc` file that `run` can run directly.
+    Compile {
+        /// The script, or `-` to read it from stdin.
+        file: String,
+        /// Where to write the compiled script. Defaults to the input path
+        /// with a `.loxc` extension.
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+}
+
+// Exit statuses from sysexits.h, as used by the book's interpreters.
+const EXIT_USAGE: u8 = 64;
+/// The program failed to scan, parse, resolve or compile.
+const EXIT_COMPILE_ERROR: u8 = 65;
+const EXIT_NO_INPUT: u8 = 66;
+/// The program raised an error that nothing caught.
+const EXIT_RUNTIME_ERROR: u8 = 70;
+const EXIT_IO_ERROR: u8 = 74;
+
+/// How the resolved program gets executed.
+#[derive(Debug, Clone, Copy)]
+struct Backend {
+    vm: bool,
+    disassemble: bool,
+    trace: bool,
 }
 
-fn main() {
-    let args = Arguments::parse();
+fn main() -> ExitCode {
+    let args = match Arguments::try_parse() {
+        Ok(args) => args,
+        // Help and version requests are not errors.
+        Err(e) => {
+            let _ = e.print();
+            return match e.use_stderr() {
+                true => ExitCode::from(EXIT_USAGE),
+                false => ExitCode::SUCCESS,
+            };
+        }
+    };
+    let backend = Backend {
+        vm: args.vm || args.disassemble || args.trace,
+        disassemble: args.disassemble,
+        trace: args.trace,
+    };
+    match args.command {
+        Some(Command::Run { file, args }) => return run_file(&file, &args, backend),
+        Some(Command::Check { file }) => return check_file(&file),
+        Some(Command::Tokens { file }) => return print_file_tokens(&file),
+        Some(Command::Ast { file, json }) => return print_file_ast(&file, json),
+        Some(Command::Fmt { file, write }) => return format_file(&file, write),
+        Some(Command::Compile { file, output }) => return compile_file(&file, output),
+        None => {}
+    }
+    if let Some(code) = args.eval {
+        return run("<eval>", &code, &[], backend);
+    }
     match args.file {
-        Some(file) => run_file(&file),
-        None => run_prompt(),
+        Some(file) => {
+            eprintln!("warning: `--file` is deprecated, use `run {file}` instead");
+            return run_file(&file, &[], backend);
+        }
+        None => return run_prompt(backend),
+    }
+}
+
+/// Reads the file at `path`, or stdin when it is `-`, and the name to
+/// report errors against.
+fn read_source(path: &str) -> Result<(String, Vec<u8>), ExitCode> {
+    if path == "-" {
+        let mut bytes = Vec::new();
+        if let Err(e) = std::io::stdin().read_to_end(&mut bytes) {
+            eprintln!("Failed to read stdin: {e}");
+            return Err(ExitCode::from(EXIT_IO_ERROR));
+        }
+        return Ok(("<stdin>".to_string(), bytes));
+    }
+    match std::fs::read(path) {
+        Ok(bytes) => return Ok((path.to_string(), bytes)),
+        Err(e) => {
+            eprintln!("Failed to read `{path}`: {e}");
+            return Err(ExitCode::from(EXIT_NO_INPUT));
+        }
     }
 }
 
-fn run_file(file: &str) {
-    let content = std::fs::read_to_string(file).expect("Failed to read file");
-    run(content);
+/// Like `read_source`, for commands that only accept source text.
+fn read_text(path: &str) -> Result<(String, String), ExitCode> {
+    let (file, bytes) = read_source(path)?;
+    return decode(file, bytes);
+}
+
+fn decode(file: String, bytes: Vec<u8>) -> Result<(String, String), ExitCode> {
+    match String::from_utf8(bytes) {
+        Ok(content) => return Ok((file, content)),
+        Err(_) => {
+            eprintln!("`{file}` is not valid UTF-8");
+            return Err(ExitCode::from(EXIT_COMPILE_ERROR));
+        }
+    }
 }
 
-fn run_prompt() {
-    let mut environment = Environment {
-        values: HashMap::new(),
-        enclosing: None,
+fn run_file(path: &str, args: &[String], backend: Backend) -> ExitCode {
+    let (file, bytes) = match read_source(path) {
+        Ok(source) => source,
+        Err(code) => return code,
     };
+    if loxc::is_loxc(&bytes) {
+        let function = match loxc::load(&bytes) {
+            Ok(function) => function,
+            Err(e) => {
+                report(&e, &file, None);
+                return ExitCode::from(EXIT_COMPILE_ERROR);
+            }
+        };
+        if let Err(e) = execute_compiled(function, args, backend) {
+            return runtime_failure(&e, &file, None);
+        }
+        return ExitCode::SUCCESS;
+    }
+    let (file, content) = match decode(file, bytes) {
+        Ok(source) => source,
+        Err(code) => return code,
+    };
+    return run(&file, &content, args, backend);
+}
 
+fn check_file(path: &str) -> ExitCode {
+    let (file, content) = match read_text(path) {
+        Ok(source) => source,
+        Err(code) => return code,
+    };
+    match analyze(&file, FileId(0), &content) {
+        Some(_) => return ExitCode::SUCCESS,
+        None => return ExitCode::from(EXIT_COMPILE_ERROR),
+    }
+}
+
+fn print_file_tokens(path: &str) -> ExitCode {
+    let (file, content) = match read_text(path) {
+        Ok(source) => source,
+        Err(code) => return code,
+    };
+    match scan_tokens(content.clone(), FileId(0)) {
+        Ok(tokens) => return write_stdout(|out| write_tokens(out, &tokens)),
+        Err(e) => {
+            report(&e, &file, Some(&content));
+            return ExitCode::from(EXIT_COMPILE_ERROR);
+        }
+    }
+}
+
+fn print_file_ast(path: &str, json: bool) -> ExitCode {
+    let (file, content) = match read_text(path) {
+        Ok(source) => source,
+        Err(code) => return code,
+    };
+    let Some(stmts) = parse(&file, FileId(0), &content) else {
+        return ExitCode::from(EXIT_COMPILE_ERROR);
+    };
+    return write_stdout(|out| match json {
+        true => writeln!(out, "{}", ast::json(&stmts)),
+        false => write!(out, "{}", ast::pretty(&stmts)),
+    });
+}
+
+/// Runs `write` against stdout. A reader that stops early, as `head` does,
+/// isn't an error; any other failure exits with `EXIT_IO_ERROR`.
+fn write_stdout(write: impl FnOnce(&mut dyn Write) -> std::io::Result<()>) -> ExitCode {
+    let mut out = std::io::stdout().lock();
+    match write(&mut out).and_then(|_| out.flush()) {
+        Ok(()) => return ExitCode::SUCCESS,
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => return ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Failed to write to stdout: {e}");
+            return ExitCode::from(EXIT_IO_ERROR);
+        }
+    }
+}
+
+/// Reformats `path`, printing the result or, with `write`, saving it over the
+/// original. Scripts that don't parse are left alone.
+fn format_file(path: &str, write: bool) -> ExitCode {
+    if write && path == "-" {
+        eprintln!("Can't rewrite stdin in place; leave out `--write`");
+        return ExitCode::from(EXIT_USAGE);
+    }
+    let (file, content) = match read_text(path) {
+        Ok(source) => source,
+        Err(code) => return code,
+    };
+    if parse(&file, FileId(0), &content).is_none() {
+        return ExitCode::from(EXIT_COMPILE_ERROR);
+    }
+    let tokens = match scan_tokens_with_comments(content, FileId(0)) {
+        Ok(tokens) => tokens,
+        Err(_) => unreachable!("the script scanned above"),
+    };
+    let formatted = formatter::format(&tokens);
+    if !write {
+        return write_stdout(|out| out.write_all(formatted.as_bytes()));
+    }
+    if let Err(e) = std::fs::write(path, formatted) {
+        eprintln!("Failed to write `{path}`: {e}");
+        return ExitCode::from(EXIT_IO_ERROR);
+    }
+    return ExitCode::SUCCESS;
+}
+
+/// Compiles `path` to bytecode and saves it next to it as `.loxc`, or to
+/// `output` when given.
+fn compile_file(path: &str, output: Option<String>) -> ExitCode {
+    let output = match output {
+        Some(output) => output,
+        None if path == "-" => {
+            eprintln!("Compiling stdin needs an `--output` path");
+            return ExitCode::from(EXIT_USAGE);
+        }
+        None => std::path::Path::new(path)
+            .with_extension("loxc")
+            .to_string_lossy()
+            .into_owned(),
+    };
+    let (file, content) = match read_text(path) {
+        Ok(source) => source,
+        Err(code) => return code,
+    };
+    let Some((stmts, _)) = analyze(&file, FileId(0), &content) else {
+        return ExitCode::from(EXIT_COMPILE_ERROR);
+    };
+    let function = match compiler::compile(&stmts) {
+        Ok(function) => function,
+        Err(e) => {
+            report(&e, &file, Some(&content));
+            return ExitCode::from(EXIT_COMPILE_ERROR);
+        }
+    };
+    if let Err(e) = std::fs::write(&output, loxc::save(&function)) {
+        eprintln!("Failed to write `{output}`: {e}");
+        return ExitCode::from(EXIT_IO_ERROR);
+    }
+    return ExitCode::SUCCESS;
+}
+
+/// Reads and runs input until end of file. Input whose brackets are still
+/// open continues on the next line, and the value of an input ending in an
+/// expression is echoed. Lines starting with `:` are commands; see `HELP`.
+/// Calling `exit` ends the session with the status it was given.
+fn run_prompt(backend: Backend) -> ExitCode {
+    let mut editor = match DefaultEditor::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            eprintln!("Failed to start the prompt: {e}");
+            return ExitCode::from(EXIT_IO_ERROR);
+        }
+    };
+    let history = history_path();
+    if let Some(history) = &history {
+        let _ = editor.load_history(history);
+    }
+
+    let mut session = Session::new(backend);
+    let mut lines = LineBuffer::default();
     loop {
-        println!("> ");
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input).unwrap();
-        let tokens = scan_tokens(input.clone());
-        match tokens {
-            Ok(tokens) => {
-                let mut parser = LoxParser::from_tokens(tokens);
-                let stmts = parser.parse().unwrap();
-                let mut interpreter = Interpreter::default();
-                if let Err(e) = interpreter.interpret(&stmts) {
-                    println!("{e:?}");
-                    return;
+        match editor.readline(lines.prompt()) {
+            Ok(line) => match lines.push(&line) {
+                Line::Incomplete => continue,
+                Line::Command(command) => {
+                    let _ = editor.add_history_entry(command.as_str());
+                    session.command(&command);
+                }
+                Line::Input(input) => {
+                    let _ = editor.add_history_entry(input.trim_end());
+                    session.eval("<stdin>", &input);
+                }
+            },
+            // Ctrl-C abandons the current input, Ctrl-D ends the session.
+            Err(ReadlineError::Interrupted) => lines.clear(),
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("Failed to read input: {e}");
+                break;
+            }
+        }
+        if session.exit.is_some() {
+            break;
+        }
+    }
+
+    if let Some(history) = &history {
+        let _ = editor.save_history(history);
+    }
+    return match session.exit {
+        Some(code) => ExitCode::from(code),
+        None => ExitCode::SUCCESS,
+    };
+}
+
+const HELP: &str = "\
+:help           Show this list
+:env            Show the variables in scope
+:tokens <code>  Show the tokens <code> scans to
+:ast <code>     Show the statements <code> parses to
+:load <file>    Run a script in this session
+:reset          Forget every variable and start over
+:time <code>    Run <code> and show how long it took
+";
+
+/// Where prompt history is kept between sessions.
+fn history_path() -> Option<std::path::PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    return Some(std::path::Path::new(&home).join(".lox_history"));
+}
+
+/// The state a prompt keeps between inputs, so globals defined by one input
+/// are visible to the next and an error doesn't end the session.
+struct Session {
+    backend: Backend,
+    interpreter: Interpreter,
+    vm: Vm,
+    /// Number of inputs read so far. Each input gets its own file id so the
+    /// resolver never confuses names from different inputs.
+    inputs: u32,
+    /// The status passed to `exit`, once a script has called it.
+    exit: Option<u8>,
+}
+
+impl Session {
+    fn new(backend: Backend) -> Self {
+        let mut vm = Vm::default();
+        vm.trace = backend.trace;
+        vm.set_global("args", args_list(&[]));
+        let mut interpreter = Interpreter::default();
+        interpreter.set_global("args", args_list(&[]));
+        return Self {
+            backend,
+            interpreter,
+            vm,
+            inputs: 0,
+            exit: None,
+        };
+    }
+
+    /// Runs a `:` command.
+    fn command(&mut self, line: &str) {
+        let (name, arg) = match line.split_once(char::is_whitespace) {
+            Some((name, arg)) => (name, arg.trim()),
+            None => (line.trim_end(), ""),
+        };
+        match name {
+            ":help" => print!("{HELP}"),
+            ":env" => {
+                if self.backend.vm {
+                    let globals = Environment {
+                        values: self.vm.globals().clone(),
+                        enclosing: None,
+                    };
+                    println!("{globals}");
+                } else {
+                    println!("{}", self.interpreter.env.borrow());
+                }
+            }
+            ":tokens" => match scan_tokens(arg.to_string(), FileId::default()) {
+                Ok(tokens) => {
+                    let _ = write_tokens(&mut std::io::stdout(), &tokens);
+                }
+                Err(e) => report(&e, "<stdin>", Some(arg)),
+            },
+            ":ast" => {
+                if let Some(stmts) = parse("<stdin>", FileId::default(), arg) {
+                    print!("{}", ast::pretty(&stmts));
+                }
+            }
+            ":load" => match std::fs::read_to_string(arg) {
+                Ok(content) => self.eval(arg, &content),
+                Err(e) => eprintln!("Failed to read `{arg}`: {e}"),
+            },
+            ":reset" => *self = Session::new(self.backend),
+            ":time" => {
+                let start = Instant::now();
+                self.eval("<stdin>", arg);
+                println!("Took {:?}", start.elapsed());
+            }
+            _ => eprintln!("Unknown command `{name}`, see `:help`."),
+        }
+    }
+
+    /// Runs `input`, read from `file`, and echoes its value.
+    fn eval(&mut self, file: &str, input: &str) {
+        let source = repl::terminate(input);
+        let id = FileId(self.inputs);
+        self.inputs += 1;
+        let Some((stmts, locals)) = analyze(file, id, &source) else {
+            return;
+        };
+        let result = if self.backend.vm {
+            compiler::compile(&stmts).and_then(|function| {
+                if self.backend.disassemble {
+                    print!("{}", debug::disassemble_function(&function));
                 }
-                environment = interpreter.env;
+                return self.vm.interpret(function);
+            })
+        } else {
+            self.interpreter.resolve(locals);
+            self.interpreter.interpret(&stmts)
+        };
+        match result {
+            Ok(Value::Nil) => {}
+            Ok(value) => println!("{value}"),
+            Err(error::Error::Exit { code }) => self.exit = Some(code),
+            Err(e) => {
+                // Code from an earlier input can raise the error, and that
+                // input is gone, so only the location can be shown then.
+                let diagnostic = Diagnostic::from(&e);
+                let source = Some(source.as_str()).filter(|_| diagnostic.span.file == id);
+                report(diagnostic, file, source);
             }
-            Err(e) => print!("{e:?}\n"),
         }
     }
 }
 
-fn run(content: String) {
-    let tokens = scan_tokens(content);
-    match tokens {
-        Ok(tokens) => {
-            let mut parser = LoxParser::from_tokens(tokens);
-            let stmts = parser.parse().unwrap();
-            let mut interpreter = Interpreter::default();
-            if let Err(e) = interpreter.interpret(&stmts) {
-                println!("{e:?}");
-                return;
+/// Runs `content`, read from `file`, on the chosen backend with `args` as
+/// the script's `args` global.
+fn run(file: &str, content: &str, args: &[String], backend: Backend) -> ExitCode {
+    let Some((stmts, locals)) = analyze(file, FileId(0), content) else {
+        return ExitCode::from(EXIT_COMPILE_ERROR);
+    };
+    let result = if backend.vm {
+        let function = match compiler::compile(&stmts) {
+            Ok(function) => function,
+            Err(e) => {
+                report(&e, file, Some(content));
+                return ExitCode::from(EXIT_COMPILE_ERROR);
             }
+        };
+        execute_compiled(function, args, backend)
+    } else {
+        let mut interpreter = Interpreter::default();
+        interpreter.set_global("args", args_list(args));
+        interpreter.resolve(locals);
+        interpreter.interpret(&stmts).map(|_| ())
+    };
+    if let Err(e) = result {
+        return runtime_failure(&e, file, Some(content));
+    }
+    return ExitCode::SUCCESS;
+}
+
+/// The exit status for a script stopped by `error`. An `exit` call picks its
+/// own status; anything else is reported as a runtime error.
+fn runtime_failure(error: &error::Error, file: &str, source: Option<&str>) -> ExitCode {
+    if let error::Error::Exit { code } = error {
+        return ExitCode::from(*code);
+    }
+    report(error, file, source);
+    return ExitCode::from(EXIT_RUNTIME_ERROR);
+}
+
+/// The value scripts see as `args`.
+fn args_list(args: &[String]) -> Value {
+    let args = args.iter().map(|arg| Value::String(arg.clone())).collect();
+    return Value::List(Rc::new(RefCell::new(args)));
+}
+
+fn write_tokens(out: &mut dyn Write, tokens: &[Token]) -> std::io::Result<()> {
+    for token in tokens {
+        writeln!(out, "{token:?}")?;
+    }
+    return Ok(());
+}
+
+/// Prints a report for an error raised by `file` to stderr, quoting `source`
+/// when it is available, so it never mixes with what the script prints. A
+/// report that can't be written is dropped; the exit status still tells what
+/// happened.
+fn report(error: impl Into<Diagnostic>, file: &str, source: Option<&str>) {
+    let diagnostic: Diagnostic = error.into();
+    let _ = writeln!(
+        std::io::stderr(),
+        "{}",
+        diagnostic.render(file, source, diagnostic::use_color())
+    );
+}
+
+/// Scans, parses and resolves `content`, printing any errors found. Spans in
+/// the result carry `id`.
+fn analyze(file: &str, id: FileId, content: &str) -> Option<(Vec<Stmt>, Locals)> {
+    let stmts = parse(file, id, content)?;
+    match resolver::resolve(&stmts) {
+        Ok(locals) => return Some((stmts, locals)),
+        Err(errors) => {
+            for e in &errors {
+                report(e, file, Some(content));
+            }
+            return None;
+        }
+    }
+}
+
+/// Scans and parses `content`, printing any errors found.
+fn parse(file: &str, id: FileId, content: &str) -> Option<Vec<Stmt>> {
+    let tokens = match scan_tokens(content.to_string(), id) {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            report(&e, file, Some(content));
+            return None;
+        }
+    };
+    let (stmts, errors) = LoxParser::from_tokens(tokens).parse();
+    if !errors.is_empty() {
+        for e in &errors {
+            report(e, file, Some(content));
         }
-        Err(e) => print!("{e:?}\n"),
+        return None;
+    }
+    return Some(stmts);
+}
+
+fn execute_compiled(
+    function: Rc<FunctionProto>,
+    args: &[String],
+    backend: Backend,
+) -> Result<(), error::Error> {
+    if backend.disassemble {
+        print!("{}", debug::disassemble_function(&function));
     }
+    let mut vm = Vm::default();
+    vm.trace = backend.trace;
+    vm.set_global("args", args_list(args));
+    return vm.interpret(function).map(|_| ());
 }
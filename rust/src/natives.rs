@@ -0,0 +1,152 @@
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    env::Environment,
+    expr::{MapKey, NativeFunction, Symbol, Value},
+};
+
+type NativeFn = fn(&mut dyn NativeContext, &[Value]) -> Result<Value, String>;
+
+/// What a native function can reach of the interpreter that called it.
+pub trait NativeContext {
+    fn get_global(&self, name: &str) -> Option<Value>;
+    /// Defines or overwrites the global `name`.
+    fn set_global(&mut self, name: &str, value: Value);
+    /// Stops the script once the native returns, with `code` as the process
+    /// exit status. Neither `catch` nor `finally` blocks run.
+    fn exit(&mut self, code: u8);
+}
+
+const NATIVES: [(&str, u8, NativeFn); 9] = [
+    ("clock", 0, clock),
+    ("len", 1, len),
+    ("push", 2, push),
+    ("pop", 1, pop),
+    ("has", 2, has),
+    ("remove", 2, remove),
+    ("keys", 1, keys),
+    ("values", 1, values),
+    ("exit", 1, exit),
+];
+
+pub fn define_natives(globals: &mut Environment) {
+    for (name, arity, callable) in NATIVES {
+        let sym = Symbol::global(name);
+        let native = NativeFunction::new(name, arity, callable);
+        globals.define(&sym, Value::NativeFunction(native));
+    }
+}
+
+fn clock(_: &mut dyn NativeContext, _: &[Value]) -> Result<Value, String> {
+    return Ok(Value::Number(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards.")
+            .as_secs() as f64,
+    ));
+}
+
+fn len(_: &mut dyn NativeContext, args: &[Value]) -> Result<Value, String> {
+    match args {
+        [Value::List(list)] => return Ok(Value::Number(list.borrow().len() as f64)),
+        [Value::Map(map)] => return Ok(Value::Number(map.borrow().len() as f64)),
+        [Value::String(s)] => return Ok(Value::Number(s.chars().count() as f64)),
+        [other] => return Err(format!("`len` expects a list, map or string, got {other}.")),
+        _ => return Err("`len` expects exactly one argument.".to_string()),
+    }
+}
+
+fn push(_: &mut dyn NativeContext, args: &[Value]) -> Result<Value, String> {
+    match args {
+        [Value::List(list), value] => {
+            list.borrow_mut().push(value.clone());
+            return Ok(Value::Nil);
+        }
+        [other, _] => return Err(format!("`push` expects a list, got {other}.")),
+        _ => return Err("`push` expects a list and a value.".to_string()),
+    }
+}
+
+fn pop(_: &mut dyn NativeContext, args: &[Value]) -> Result<Value, String> {
+    match args {
+        [Value::List(list)] => match list.borrow_mut().pop() {
+            Some(value) => return Ok(value),
+            None => return Err("Can't pop from an empty list.".to_string()),
+        },
+        [other] => return Err(format!("`pop` expects a list, got {other}.")),
+        _ => return Err("`pop` expects exactly one argument.".to_string()),
+    }
+}
+
+fn has(_: &mut dyn NativeContext, args: &[Value]) -> Result<Value, String> {
+    match args {
+        [Value::Map(map), key] => {
+            let key = MapKey::try_from(key)?;
+            return Ok(Value::Bool(map.borrow().contains_key(&key)));
+        }
+        [other, _] => return Err(format!("`has` expects a map, got {other}.")),
+        _ => return Err("`has` expects a map and a key.".to_string()),
+    }
+}
+
+fn remove(_: &mut dyn NativeContext, args: &[Value]) -> Result<Value, String> {
+    match args {
+        [Value::Map(map), key] => {
+            let key = MapKey::try_from(key)?;
+            return Ok(map.borrow_mut().remove(&key).unwrap_or(Value::Nil));
+        }
+        [other, _] => return Err(format!("`remove` expects a map, got {other}.")),
+        _ => return Err("`remove` expects a map and a key.".to_string()),
+    }
+}
+
+fn keys(_: &mut dyn NativeContext, args: &[Value]) -> Result<Value, String> {
+    match args {
+        [Value::Map(map)] => {
+            let keys = map
+                .borrow()
+                .iter()
+                .map(|(key, _)| Value::from(key.clone()))
+                .collect();
+            return Ok(Value::List(Rc::new(RefCell::new(keys))));
+        }
+        [other] => return Err(format!("`keys` expects a map, got {other}.")),
+        _ => return Err("`keys` expects exactly one argument.".to_string()),
+    }
+}
+
+fn values(_: &mut dyn NativeContext, args: &[Value]) -> Result<Value, String> {
+    match args {
+        [Value::Map(map)] => {
+            let values = map
+                .borrow()
+                .iter()
+                .map(|(_, value)| value.clone())
+                .collect();
+            return Ok(Value::List(Rc::new(RefCell::new(values))));
+        }
+        [other] => return Err(format!("`values` expects a map, got {other}.")),
+        _ => return Err("`values` expects exactly one argument.".to_string()),
+    }
+}
+
+fn exit(context: &mut dyn NativeContext, args: &[Value]) -> Result<Value, String> {
+    match args {
+        // A process only passes the low 8 bits of its status on, so larger
+        // codes could turn a failure into success.
+        [Value::Number(code)] if code.fract() == 0.0 && (0.0..=255.0).contains(code) => {
+            context.exit(*code as u8);
+            return Ok(Value::Nil);
+        }
+        [other] => {
+            return Err(format!(
+                "`exit` expects a whole number from 0 to 255, got {other}."
+            ))
+        }
+        _ => return Err("`exit` expects exactly one argument.".to_string()),
+    }
+}
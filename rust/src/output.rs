@@ -0,0 +1,105 @@
+//! Where scripts write. `print` statements, and the VM's trace when it is
+//! on, go to the `Output` of the interpreter running them, so a host can
+//! capture or redirect everything a script prints.
+
+use std::io::{self, Write};
+
+use crate::error::Error;
+use crate::expr::{SourceLocation, Value};
+
+/// ```
+/// use rust_interpreter::{Interpreter, Lox, Output};
+///
+/// let interpreter = Interpreter::builder().output(Output::buffer()).build();
+/// let mut lox = Lox::from(interpreter);
+/// lox.eval("print 1 + 2; print nil;").unwrap();
+/// assert_eq!(lox.output().take(), b"3\nnil\n");
+/// ```
+#[derive(Default)]
+pub enum Output {
+    /// The process's standard output.
+    #[default]
+    Stdout,
+    /// Kept in memory until the host reads it with [`Output::contents`] or
+    /// [`Output::take`].
+    Buffer(Vec<u8>),
+    /// Any other writer, such as a file or a socket.
+    Writer(Box<dyn Write>),
+}
+
+impl Output {
+    /// An empty in-memory buffer.
+    pub fn buffer() -> Self {
+        return Output::Buffer(Vec::new());
+    }
+
+    /// Everything written so far, if this is a buffer.
+    pub fn contents(&self) -> Option<&[u8]> {
+        match self {
+            Output::Buffer(buffer) => return Some(buffer),
+            _ => return None,
+        }
+    }
+
+    /// Empties a buffer and returns what it held. Other outputs return
+    /// nothing.
+    pub fn take(&mut self) -> Vec<u8> {
+        match self {
+            Output::Buffer(buffer) => return std::mem::take(buffer),
+            _ => return Vec::new(),
+        }
+    }
+
+    /// Writes `value` and a newline, as the `print` statement at `location`.
+    pub(crate) fn print(&mut self, value: &Value, location: SourceLocation) -> Result<(), Error> {
+        return writeln!(self, "{value}").map_err(|e| write_error(e, location));
+    }
+
+    /// Flushes what a script wrote before handing back `result`, the
+    /// outcome of running it. A failed flush only replaces a success.
+    pub(crate) fn finish(&mut self, result: Result<Value, Error>) -> Result<Value, Error> {
+        let flushed = self.flush();
+        let value = result?;
+        if let Err(e) = flushed {
+            return Err(write_error(e, SourceLocation::none()));
+        }
+        return Ok(value);
+    }
+}
+
+fn write_error(error: io::Error, location: SourceLocation) -> Error {
+    return Error::RunTimeException {
+        message: format!("Failed to write output: {error}."),
+        location,
+    };
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Stdout => return io::stdout().write(buf),
+            Output::Buffer(buffer) => return buffer.write(buf),
+            Output::Writer(writer) => return writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout => return io::stdout().flush(),
+            Output::Buffer(_) => return Ok(()),
+            Output::Writer(writer) => return writer.flush(),
+        }
+    }
+}
+
+impl From<Vec<u8>> for Output {
+    fn from(buffer: Vec<u8>) -> Self {
+        return Output::Buffer(buffer);
+    }
+}
+
+impl From<Box<dyn Write>> for Output {
+    fn from(writer: Box<dyn Write>) -> Self {
+        return Output::Writer(writer);
+    }
+}
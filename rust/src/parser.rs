@@ -1,16 +1,24 @@
 use crate::{
-    error::Error,
+    error::{Error, FunctionKind},
     expr::{
-        BinaryOp, Expr, FunDecl, Literal, LogicalOp, SourceLocation, Stmt, Symbol, UnaryOp, Value,
+        BinaryOp, ClassDecl, Expr, ExprKind, FunDecl, LambdaDecl, Literal, LogicalOp, Param,
+        SourceLocation, Stmt, StmtKind, Symbol, UnaryOp,
     },
-    scanner::{self, TokenType},
-    Token,
+    scanner::{self, Token, TokenType},
 };
 
 pub struct LoxParser {
     pub tokens: Vec<Token>,
     pub current: usize,
-    in_loop: bool,
+    /// Labels of the loops enclosing the current statement, innermost last.
+    /// Unlabelled loops are `None`. Reset on entering a function body.
+    loops: Vec<Option<String>>,
+    /// Errors reported so far. Parsing resumes at the next statement after
+    /// each one.
+    errors: Vec<Error>,
+    /// How many blocks enclose the current statement. Recovery inside a block
+    /// stops at its closing `}` so the block can still end there.
+    blocks: usize,
 }
 
 impl Default for LoxParser {
@@ -18,7 +26,9 @@ impl Default for LoxParser {
         return Self {
             tokens: vec![],
             current: 0,
-            in_loop: false,
+            loops: Vec::new(),
+            errors: Vec::new(),
+            blocks: 0,
         };
     }
 }
@@ -28,9 +38,22 @@ impl LoxParser {
         return Self {
             tokens,
             current: 0,
-            in_loop: false,
+            loops: Vec::new(),
+            errors: Vec::new(),
+            blocks: 0,
         };
     }
+
+    /// Location of the token just consumed.
+    fn previous(&self) -> SourceLocation {
+        return self.tokens[self.current - 1].location();
+    }
+
+    /// `start` with its span extended over every token consumed since.
+    fn since(&self, start: SourceLocation) -> SourceLocation {
+        return start.to(self.tokens[self.current - 1].span);
+    }
+
     fn expr(&mut self) -> Result<Expr, Error> {
         return self.assignment();
     }
@@ -40,14 +63,34 @@ impl LoxParser {
         if self.match_one_of(vec![TokenType::Equal]) {
             let equals = &self.tokens[self.current - 1].clone(); // idk what i am doing
             let value = self.assignment()?;
+            let location = self.since(expr.location);
 
-            if let Expr::Variable(v) = expr {
-                return Ok(Expr::Assign(v, Box::new(value)));
+            if let ExprKind::Variable(v) = expr.kind {
+                return Ok(Expr::new(ExprKind::Assign(v, Box::new(value)), location));
+            }
+            if let ExprKind::Get(object, name) = expr.kind {
+                return Ok(Expr::new(
+                    ExprKind::Set(object, name, Box::new(value)),
+                    location,
+                ));
+            }
+            if let ExprKind::Subscript {
+                value: lhs,
+                slice,
+                source_location,
+            } = expr.kind
+            {
+                let kind = ExprKind::SetItem {
+                    lhs,
+                    slice,
+                    rhs: Box::new(value),
+                    source_location,
+                };
+                return Ok(Expr::new(kind, location));
             }
 
             return Err(Error::InvalidAssignment {
-                line: equals.line,
-                col: equals.col,
+                location: equals.location(),
             });
         }
         return Ok(expr);
@@ -58,7 +101,11 @@ impl LoxParser {
         while self.match_one_of(vec![TokenType::BangEqual, TokenType::EqualEqual]) {
             let op: BinaryOp = BinaryOp::from_token(&self.tokens[self.current - 1]);
             let right: Expr = self.comparision()?;
-            expr = Expr::Binary(Box::new(expr), op, Box::new(right));
+            let location = self.since(expr.location);
+            expr = Expr::new(
+                ExprKind::Binary(Box::new(expr), op, Box::new(right)),
+                location,
+            );
         }
 
         return Ok(expr);
@@ -69,7 +116,11 @@ impl LoxParser {
         while self.match_one_of(vec![TokenType::Or]) {
             let op = LogicalOp::Or;
             let right = self.and()?;
-            expr = Expr::Logical(Box::new(expr), op, Box::new(right));
+            let location = self.since(expr.location);
+            expr = Expr::new(
+                ExprKind::Logical(Box::new(expr), op, Box::new(right)),
+                location,
+            );
         }
         return Ok(expr);
     }
@@ -79,7 +130,11 @@ impl LoxParser {
         while self.match_one_of(vec![TokenType::And]) {
             let op = LogicalOp::And;
             let right = self.equality()?;
-            expr = Expr::Logical(Box::new(expr), op, Box::new(right));
+            let location = self.since(expr.location);
+            expr = Expr::new(
+                ExprKind::Logical(Box::new(expr), op, Box::new(right)),
+                location,
+            );
         }
         return Ok(expr);
     }
@@ -94,6 +149,13 @@ impl LoxParser {
         return false;
     }
 
+    fn check_next(&self, token: TokenType) -> bool {
+        match self.tokens.get(self.current + 1) {
+            Some(next) => next.ty == token,
+            None => false,
+        }
+    }
+
     fn check_type(&self, token: TokenType) -> bool {
         if self.is_at_end() {
             return false;
@@ -127,7 +189,11 @@ impl LoxParser {
         ]) {
             let op: BinaryOp = BinaryOp::from_token(&self.tokens[self.current - 1]);
             let right: Expr = self.term()?;
-            expr = Expr::Binary(Box::new(expr), op, Box::new(right));
+            let location = self.since(expr.location);
+            expr = Expr::new(
+                ExprKind::Binary(Box::new(expr), op, Box::new(right)),
+                location,
+            );
         }
         return Ok(expr);
     }
@@ -138,7 +204,11 @@ impl LoxParser {
         while self.match_one_of(vec![TokenType::Minus, TokenType::Plus]) {
             let op: BinaryOp = BinaryOp::from_token(&self.tokens[self.current - 1]);
             let right: Expr = self.factor()?;
-            expr = Expr::Binary(Box::new(expr), op, Box::new(right))
+            let location = self.since(expr.location);
+            expr = Expr::new(
+                ExprKind::Binary(Box::new(expr), op, Box::new(right)),
+                location,
+            );
         }
         return Ok(expr);
     }
@@ -149,33 +219,58 @@ impl LoxParser {
         while self.match_one_of(vec![TokenType::Slash, TokenType::Star]) {
             let op: BinaryOp = BinaryOp::from_token(&self.tokens[self.current - 1]);
             let right: Expr = self.unary()?;
-            expr = Expr::Binary(Box::new(expr), op, Box::new(right))
+            let location = self.since(expr.location);
+            expr = Expr::new(
+                ExprKind::Binary(Box::new(expr), op, Box::new(right)),
+                location,
+            );
         }
         return Ok(expr);
     }
 
     fn unary(&mut self) -> Result<Expr, Error> {
         if self.match_one_of(vec![TokenType::Bang, TokenType::Minus]) {
+            let start = self.previous();
             let op: UnaryOp = UnaryOp::from_token(&self.tokens[self.current - 1]);
             let right: Expr = self.unary()?;
-            return Ok(Expr::Unary(op, Box::new(right)));
+            return Ok(Expr::new(
+                ExprKind::Unary(op, Box::new(right)),
+                self.since(start),
+            ));
         }
 
         return self.call();
     }
 
     fn call(&mut self) -> Result<Expr, Error> {
-        let mut expr = self.primary();
+        let mut expr = self.primary()?;
 
         loop {
             if self.match_one_of(vec![TokenType::LeftParen]) {
-                expr = self.finish_call(expr?);
+                expr = self.finish_call(expr)?;
+            } else if self.match_one_of(vec![TokenType::Dot]) {
+                let name: Symbol = self
+                    .consume(TokenType::Identifier, "Expect property name after `.`.")?
+                    .into();
+                let location = self.since(expr.location);
+                expr = Expr::new(ExprKind::Get(Box::new(expr), name), location);
+            } else if self.match_one_of(vec![TokenType::LeftBracket]) {
+                let slice = self.expr()?;
+                let bracket =
+                    self.consume(TokenType::RightBracket, "Expect `]` after subscript.")?;
+                let location = self.since(expr.location);
+                let kind = ExprKind::Subscript {
+                    value: Box::new(expr),
+                    slice: Box::new(slice),
+                    source_location: bracket.location(),
+                };
+                expr = Expr::new(kind, location);
             } else {
                 break;
             }
         }
 
-        return expr;
+        return Ok(expr);
     }
 
     fn finish_call(&mut self, expr: Expr) -> Result<Expr, Error> {
@@ -184,11 +279,19 @@ impl LoxParser {
             loop {
                 if args.len() >= 255 {
                     return Err(Error::TooManyArguments {
-                        line: self.peek().line,
-                        col: self.peek().col,
+                        location: self.peek().location(),
                     });
                 }
-                args.push(self.expr()?);
+                if self.match_one_of(vec![TokenType::Ellipsis]) {
+                    let start = self.previous();
+                    let list = self.expr()?;
+                    args.push(Expr::new(
+                        ExprKind::Spread(Box::new(list)),
+                        self.since(start),
+                    ));
+                } else {
+                    args.push(self.expr()?);
+                }
                 if !self.match_one_of(vec![TokenType::Comma]) {
                     break;
                 }
@@ -196,30 +299,38 @@ impl LoxParser {
         }
 
         let paren = self.consume(TokenType::RightParen, "Expect `)` after arguments")?;
-        let source_location = SourceLocation {
-            line: paren.line,
-            col: paren.col,
-        };
-
-        return Ok(Expr::Call(Box::new(expr), source_location, args));
+        let location = self.since(expr.location);
+        return Ok(Expr::new(
+            ExprKind::Call(Box::new(expr), paren.location(), args),
+            location,
+        ));
     }
 
     fn primary(&mut self) -> Result<Expr, Error> {
         if self.match_one_of(vec![TokenType::False]) {
-            return Ok(Expr::Literal(crate::expr::Literal::False));
+            return Ok(Expr::new(
+                ExprKind::Literal(Literal::False),
+                self.previous(),
+            ));
         }
         if self.match_one_of(vec![TokenType::True]) {
-            return Ok(Expr::Literal(crate::expr::Literal::True));
+            return Ok(Expr::new(ExprKind::Literal(Literal::True), self.previous()));
         }
         if self.match_one_of(vec![TokenType::Nil]) {
-            return Ok(Expr::Literal(crate::expr::Literal::Nil));
+            return Ok(Expr::new(ExprKind::Literal(Literal::Nil), self.previous()));
         }
         if self.match_one_of(vec![TokenType::Number, TokenType::String]) {
+            let location = self.previous();
             let prev = &self.tokens[self.current - 1].literal;
             match &prev {
-                Some(scanner::Literal::Number(n)) => return Ok(Expr::Literal(Literal::Number(*n))),
+                Some(scanner::Literal::Number(n)) => {
+                    return Ok(Expr::new(ExprKind::Literal(Literal::Number(*n)), location))
+                }
                 Some(scanner::Literal::Str(s)) => {
-                    return Ok(Expr::Literal(Literal::String(s.clone())))
+                    return Ok(Expr::new(
+                        ExprKind::Literal(Literal::String(s.clone())),
+                        location,
+                    ))
                 }
                 Some(l) => panic!(
                     "internal error in parser: when parsing number, found literal {:?}",
@@ -228,26 +339,74 @@ impl LoxParser {
                 None => panic!("internal error in parser: when parsing number, found no literal"),
             }
         }
+        if self.match_one_of(vec![TokenType::Super]) {
+            let keyword = self.previous();
+            let _ = self.consume(TokenType::Dot, "Expect `.` after `super`.")?;
+            let method: Symbol = self
+                .consume(TokenType::Identifier, "Expect superclass method name.")?
+                .into();
+            return Ok(Expr::new(
+                ExprKind::Super(keyword, method),
+                self.since(keyword),
+            ));
+        }
+        if self.match_one_of(vec![TokenType::Lambda, TokenType::Fun]) {
+            return self.lambda();
+        }
+        if self.match_one_of(vec![TokenType::This]) {
+            let keyword = self.previous();
+            return Ok(Expr::new(ExprKind::This(keyword), keyword));
+        }
         if self.match_one_of(vec![TokenType::Identifier]) {
-            let name = &self.tokens[self.current - 1];
-            let symbol_name = String::from_utf8(name.lexeme.clone()).unwrap();
-            let name = Symbol {
-                name: symbol_name,
-                line: name.line,
-                col: name.col,
-            };
-            return Ok(Expr::Variable(name));
+            let name: Symbol = self.tokens[self.current - 1].clone().into();
+            return Ok(Expr::new(ExprKind::Variable(name), self.previous()));
+        }
+        if self.match_one_of(vec![TokenType::LeftBracket]) {
+            let start = self.previous();
+            let mut elements = Vec::new();
+            if !self.check_type(TokenType::RightBracket) {
+                loop {
+                    elements.push(self.expr()?);
+                    if !self.match_one_of(vec![TokenType::Comma]) {
+                        break;
+                    }
+                }
+            }
+            let _ = self.consume(TokenType::RightBracket, "Expect `]` after list elements.")?;
+            return Ok(Expr::new(ExprKind::List(elements), self.since(start)));
+        }
+        if self.match_one_of(vec![TokenType::LeftBrace]) {
+            // A `{` can only start a block at statement level, so in expression
+            // position it is always a map literal.
+            let brace = self.previous();
+            let mut entries = Vec::new();
+            if !self.check_type(TokenType::RightBrace) {
+                loop {
+                    let key = self.expr()?;
+                    let _ = self.consume(TokenType::Colon, "Expect `:` after map key.")?;
+                    let value = self.expr()?;
+                    entries.push((key, value));
+                    if !self.match_one_of(vec![TokenType::Comma]) {
+                        break;
+                    }
+                }
+            }
+            let _ = self.consume(TokenType::RightBrace, "Expect `}` after map entries.")?;
+            return Ok(Expr::new(ExprKind::Map(entries, brace), self.since(brace)));
         }
         if self.match_one_of(vec![TokenType::LeftParen]) {
+            let start = self.previous();
             let expr: Expr = self.expr()?;
-            let _ = self.consume(TokenType::RightParen, "Expect `)` after expression.");
-            return Ok(Expr::Grouping(Box::new(expr)));
+            let _ = self.consume(TokenType::RightParen, "Expect `)` after expression.")?;
+            return Ok(Expr::new(
+                ExprKind::Grouping(Box::new(expr)),
+                self.since(start),
+            ));
         }
 
         return Err(Error::ExpectedExpression {
             token_type: self.peek().ty,
-            line: self.peek().line,
-            col: self.peek().col,
+            location: self.peek().location(),
         });
     }
 
@@ -257,22 +416,31 @@ impl LoxParser {
         }
         return Err(Error::TokenMismatch {
             expected: tok,
-            found: self.peek().clone(),
+            found: Box::new(self.peek().clone()),
             maybe_on_err_string: Some(on_err_str.into()),
         });
     }
 
-    fn synchronize(&mut self) -> () {
-        self.advance();
+    fn synchronize(&mut self) {
+        // Braces opened while skipping, which have to be closed before a `}`
+        // can end the enclosing block.
+        let mut nested = 0;
         while !self.is_at_end() {
-            let prev = &self.tokens[self.current - 1];
-
-            if prev.ty == TokenType::Semicolon {
+            if self.check_type(TokenType::RightBrace) && nested == 0 && self.blocks > 0 {
                 return;
             }
+            let skipped = self.advance();
+            match skipped.ty {
+                TokenType::Semicolon if nested == 0 => return,
+                TokenType::LeftBrace => nested += 1,
+                TokenType::RightBrace if nested > 0 => nested -= 1,
+                _ => (),
+            }
+            if nested > 0 {
+                continue;
+            }
 
             match self.peek().ty {
-                TokenType::Semicolon => return,
                 TokenType::Class
                 | TokenType::Fun
                 | TokenType::Var
@@ -280,36 +448,51 @@ impl LoxParser {
                 | TokenType::If
                 | TokenType::While
                 | TokenType::Print
-                | TokenType::Return => return,
+                | TokenType::Return
+                | TokenType::Try
+                | TokenType::Throw
+                | TokenType::Break
+                | TokenType::Continue => return,
                 _ => (),
             }
-
-            self.advance();
         }
     }
 
-    pub fn parse(&mut self) -> Result<Vec<Stmt>, Error> {
+    /// Parses every statement, recovering after each syntax error so they
+    /// can all be reported at once. The statements are only complete when no
+    /// errors are returned.
+    pub fn parse(&mut self) -> (Vec<Stmt>, Vec<Error>) {
         let mut statements: Vec<Stmt> = Vec::new();
         while !self.is_at_end() {
-            statements.push(self.declaration()?);
+            if let Some(stmt) = self.recovering_declaration() {
+                statements.push(stmt);
+            }
+        }
+        return (statements, std::mem::take(&mut self.errors));
+    }
+
+    /// Parses a declaration, or records its error and skips to where the next
+    /// one is likely to start.
+    fn recovering_declaration(&mut self) -> Option<Stmt> {
+        match self.declaration() {
+            Ok(stmt) => return Some(stmt),
+            Err(e) => {
+                self.errors.push(e);
+                self.synchronize();
+                return None;
+            }
         }
-        return Ok(statements);
     }
 
     fn statement(&mut self) -> Result<Stmt, Error> {
         if self.match_one_of(vec![TokenType::For]) {
             return self.for_statement();
         }
-        if self.match_one_of(vec![TokenType::Break]) {
-            println!("In Loop: {0}", self.in_loop);
-            if !self.in_loop {
-                return Err(Error::BreakNotInLoop {
-                    line: self.peek().line,
-                    col: self.peek().col,
-                });
-            }
-            let _ = self.consume(TokenType::Semicolon, "Expected `;` after break.");
-            return Ok(Stmt::Break);
+        if self.match_one_of(vec![TokenType::Break, TokenType::Continue]) {
+            return self.jump_statement();
+        }
+        if self.check_type(TokenType::Identifier) && self.check_next(TokenType::Colon) {
+            return self.labeled_statement();
         }
         if self.match_one_of(vec![TokenType::If]) {
             return self.if_statement();
@@ -320,6 +503,12 @@ impl LoxParser {
         if self.match_one_of(vec![TokenType::Return]) {
             return self.return_statement();
         }
+        if self.match_one_of(vec![TokenType::Throw]) {
+            return self.throw_statement();
+        }
+        if self.match_one_of(vec![TokenType::Try]) {
+            return self.try_statement();
+        }
         if self.match_one_of(vec![TokenType::While]) {
             return self.while_statement();
         }
@@ -330,47 +519,43 @@ impl LoxParser {
     }
 
     fn print_statement(&mut self) -> Result<Stmt, Error> {
-        let value: Expr = self.expr().unwrap();
-        if let Err(res) = self.consume(TokenType::Semicolon, "Expect `;` after value") {
-            return Err(res);
-        }
-        return Ok(Stmt::Print(value));
+        let start = self.previous();
+        let value: Expr = self.expr()?;
+        self.consume(TokenType::Semicolon, "Expect `;` after value")?;
+        return Ok(Stmt::new(StmtKind::Print(value), self.since(start)));
     }
 
     fn expr_statement(&mut self) -> Result<Stmt, Error> {
-        let value: Expr = self.expr().unwrap();
-        if let Err(res) = self.consume(TokenType::Semicolon, "Expect `;` after expression") {
-            return Err(res);
-        }
-        return Ok(Stmt::Expr(value));
+        let value: Expr = self.expr()?;
+        self.consume(TokenType::Semicolon, "Expect `;` after expression")?;
+        let location = self.since(value.location);
+        return Ok(Stmt::new(StmtKind::Expr(value), location));
     }
 
     fn declaration(&mut self) -> Result<Stmt, Error> {
-        if self.match_one_of(vec![TokenType::Fun]) {
-            return self.function("function");
+        if self.match_one_of(vec![TokenType::Class]) {
+            return self.class_declaration();
         }
-        if self.match_one_of(vec![TokenType::Var]) {
-            let stmt = self.var_declaration();
-            if let Err(_) = stmt {
-                self.synchronize();
+        if self.match_one_of(vec![TokenType::Fun]) {
+            if self.check_type(TokenType::Identifier) {
+                let start = self.previous();
+                let function = self.function(FunctionKind::Function)?;
+                return Ok(Stmt::new(StmtKind::FunDecl(function), self.since(start)));
             }
-            return stmt;
+            // `fun (...)` without a name is an anonymous function expression.
+            self.current -= 1;
         }
-        let stmt = self.statement();
-        if let Err(_) = stmt {
-            self.synchronize();
+        if self.match_one_of(vec![TokenType::Var]) {
+            return self.var_declaration();
         }
-        return stmt;
+        return self.statement();
     }
 
     fn var_declaration(&mut self) -> Result<Stmt, Error> {
-        let name = self.consume(TokenType::Identifier, "Expect variable name")?;
-        let symbol_name = String::from_utf8(name.lexeme).unwrap();
-        let name = Symbol {
-            name: symbol_name,
-            line: name.line,
-            col: name.col,
-        };
+        let start = self.previous();
+        let name: Symbol = self
+            .consume(TokenType::Identifier, "Expect variable name")?
+            .into();
         let mut initializer: Option<Expr> = None;
         if self.match_one_of(vec![TokenType::Equal]) {
             initializer = Some(self.expr()?);
@@ -378,141 +563,392 @@ impl LoxParser {
         let _ = self.consume(
             TokenType::Semicolon,
             "Expect `;` after variable declaration",
-        );
-        return Ok(Stmt::VarDecl(name, initializer));
+        )?;
+        return Ok(Stmt::new(
+            StmtKind::VarDecl(name, initializer),
+            self.since(start),
+        ));
     }
 
     fn block(&mut self) -> Result<Stmt, Error> {
+        let start = self.previous();
         let mut statements: Vec<Stmt> = Vec::new();
+        self.blocks += 1;
         while !self.check_type(TokenType::RightBrace) && !self.is_at_end() {
-            statements.push(self.declaration()?);
+            if let Some(stmt) = self.recovering_declaration() {
+                statements.push(stmt);
+            }
         }
-        let _ = self.consume(TokenType::RightBrace, "Expect `}` after block");
-        return Ok(Stmt::Block(statements));
+        self.blocks -= 1;
+        let _ = self.consume(TokenType::RightBrace, "Expect `}` after block")?;
+        return Ok(Stmt::new(StmtKind::Block(statements), self.since(start)));
     }
 
     fn if_statement(&mut self) -> Result<Stmt, Error> {
-        let _ = self.consume(TokenType::LeftParen, "Expect `(` after `if`.");
+        let start = self.previous();
+        let _ = self.consume(TokenType::LeftParen, "Expect `(` after `if`.")?;
         let condition = self.expr()?;
-        let _ = self.consume(TokenType::RightParen, "Expect `)` after `if`.");
+        let _ = self.consume(TokenType::RightParen, "Expect `)` after `if`.")?;
         let then_branch = Box::new(self.statement()?);
         let mut else_branch = None;
         if self.match_one_of(vec![TokenType::Else]) {
             else_branch = Some(Box::new(self.statement()?));
         }
-        return Ok(Stmt::If(condition, then_branch, else_branch));
+        return Ok(Stmt::new(
+            StmtKind::If(condition, then_branch, else_branch),
+            self.since(start),
+        ));
+    }
+
+    fn jump_statement(&mut self) -> Result<Stmt, Error> {
+        let keyword = self.tokens[self.current - 1].clone();
+        let source_location = keyword.location();
+        let mut label: Option<Symbol> = None;
+        if self.check_type(TokenType::Identifier) {
+            label = Some(self.advance().into());
+        }
+
+        if self.loops.is_empty() {
+            if keyword.ty == TokenType::Break {
+                return Err(Error::BreakNotInLoop {
+                    location: keyword.location(),
+                });
+            }
+            return Err(Error::ContinueNotInLoop {
+                location: keyword.location(),
+            });
+        }
+        if let Some(label) = &label {
+            if !self.loops.contains(&Some(label.name.clone())) {
+                return Err(Error::UndefinedLabel {
+                    name: label.name.clone(),
+                    location: label.location(),
+                });
+            }
+        }
+
+        if keyword.ty == TokenType::Break {
+            let _ = self.consume(TokenType::Semicolon, "Expect `;` after `break`.")?;
+            return Ok(Stmt::new(
+                StmtKind::Break(source_location, label),
+                self.since(source_location),
+            ));
+        }
+        let _ = self.consume(TokenType::Semicolon, "Expect `;` after `continue`.")?;
+        return Ok(Stmt::new(
+            StmtKind::Continue(source_location, label),
+            self.since(source_location),
+        ));
+    }
+
+    fn labeled_statement(&mut self) -> Result<Stmt, Error> {
+        let label: Symbol = self.advance().into();
+        let _ = self.consume(TokenType::Colon, "Expect `:` after label.")?;
+        if self.loops.contains(&Some(label.name.clone())) {
+            return Err(Error::DuplicateLabel {
+                location: label.location(),
+                name: label.name,
+            });
+        }
+        let start = label.location();
+        if self.match_one_of(vec![TokenType::While]) {
+            return self.while_statement_labeled(Some(label), start);
+        }
+        if self.match_one_of(vec![TokenType::For]) {
+            return self.for_statement_labeled(Some(label), start);
+        }
+        return Err(Error::TokenMismatch {
+            expected: TokenType::While,
+            found: Box::new(self.peek()),
+            maybe_on_err_string: Some("Expect a loop after label.".into()),
+        });
     }
 
     fn while_statement(&mut self) -> Result<Stmt, Error> {
-        self.in_loop = true;
-        let _ = self.consume(TokenType::LeftParen, "Expect `(` after `if`.");
+        return self.while_statement_labeled(None, self.previous());
+    }
+
+    fn while_statement_labeled(
+        &mut self,
+        label: Option<Symbol>,
+        start: SourceLocation,
+    ) -> Result<Stmt, Error> {
+        let _ = self.consume(TokenType::LeftParen, "Expect `(` after `while`.")?;
         let condition = self.expr()?;
-        let _ = self.consume(TokenType::RightParen, "Expect `)` after `if`.");
-        let while_branch = Box::new(self.statement()?);
-        self.in_loop = false;
-        return Ok(Stmt::While(condition, while_branch));
+        let _ = self.consume(TokenType::RightParen, "Expect `)` after condition.")?;
+        let body = self.loop_body(&label)?;
+        let kind = StmtKind::While {
+            condition,
+            body: Box::new(body),
+            increment: None,
+            label,
+        };
+        return Ok(Stmt::new(kind, self.since(start)));
+    }
+
+    fn loop_body(&mut self, label: &Option<Symbol>) -> Result<Stmt, Error> {
+        self.loops
+            .push(label.as_ref().map(|label| label.name.clone()));
+        let body = self.statement();
+        self.loops.pop();
+        return body;
     }
 
     fn for_statement(&mut self) -> Result<Stmt, Error> {
-        self.in_loop = true;
-        let _ = self.consume(TokenType::LeftParen, "Expect `(` after `for`.");
-        let mut initializer: Option<Stmt> = None;
-        if self.match_one_of(vec![TokenType::Semicolon]) {
-            initializer = None;
+        return self.for_statement_labeled(None, self.previous());
+    }
+
+    fn for_statement_labeled(
+        &mut self,
+        label: Option<Symbol>,
+        start: SourceLocation,
+    ) -> Result<Stmt, Error> {
+        let _ = self.consume(TokenType::LeftParen, "Expect `(` after `for`.")?;
+        let initializer: Option<Stmt> = if self.match_one_of(vec![TokenType::Semicolon]) {
+            None
         } else if self.match_one_of(vec![TokenType::Var]) {
-            initializer = Some(self.var_declaration()?);
+            Some(self.var_declaration()?)
         } else {
-            initializer = Some(self.expr_statement()?);
-        }
+            Some(self.expr_statement()?)
+        };
 
         let mut condition: Option<Expr> = None;
         if !self.check_type(TokenType::Semicolon) {
             condition = Some(self.expr()?);
         }
-        let _ = self.consume(TokenType::Semicolon, "Expect `;` after loop condition.");
+        let _ = self.consume(TokenType::Semicolon, "Expect `;` after loop condition.")?;
         let mut increment: Option<Expr> = None;
         if !self.check_type(TokenType::RightParen) {
             increment = Some(self.expr()?);
         }
-        let _ = self.consume(TokenType::RightParen, "Expect `)` after for clauses.");
-        let mut body: Stmt = self.statement()?;
+        let _ = self.consume(TokenType::RightParen, "Expect `)` after for clauses.")?;
+        let body: Stmt = self.loop_body(&label)?;
+
+        // The increment lives on the loop itself rather than at the end of the
+        // body so that `continue` still runs it.
+        let condition =
+            condition.unwrap_or_else(|| Expr::new(ExprKind::Literal(Literal::True), start));
+        let location = self.since(start);
+        let mut body = Stmt::new(
+            StmtKind::While {
+                condition,
+                body: Box::new(body),
+                increment,
+                label,
+            },
+            location,
+        );
 
-        if let Some(expr) = increment {
-            body = Stmt::Block(vec![body, Stmt::Expr(expr)]);
+        if let Some(initializer) = initializer {
+            body = Stmt::new(StmtKind::Block(vec![initializer, body]), location);
         }
 
-        if let None = condition {
-            condition = Some(Expr::Literal(Literal::True));
+        return Ok(body);
+    }
+
+    fn class_declaration(&mut self) -> Result<Stmt, Error> {
+        let start = self.previous();
+        let name: Symbol = self
+            .consume(TokenType::Identifier, "Expect class name.")?
+            .into();
+        let mut superclass = None;
+        if self.match_one_of(vec![TokenType::Less]) {
+            superclass = Some(
+                self.consume(TokenType::Identifier, "Expect superclass name.")?
+                    .into(),
+            );
         }
-        body = Stmt::While(condition.unwrap(), Box::new(body));
+        let _ = self.consume(TokenType::LeftBrace, "Expect `{` before class body.")?;
 
-        if let Some(_) = initializer {
-            body = Stmt::Block(vec![initializer.unwrap(), body]);
+        let mut methods = Vec::new();
+        while !self.check_type(TokenType::RightBrace) && !self.is_at_end() {
+            methods.push(self.function(FunctionKind::Method)?);
         }
 
-        self.in_loop = false;
-        return Ok(body);
+        let _ = self.consume(TokenType::RightBrace, "Expect `}` after class body.")?;
+        let kind = StmtKind::ClassDecl(ClassDecl {
+            name,
+            superclass,
+            methods,
+        });
+        return Ok(Stmt::new(kind, self.since(start)));
     }
 
-    fn function(&mut self, kind: &str) -> Result<Stmt, Error> {
+    fn function(&mut self, kind: FunctionKind) -> Result<FunDecl, Error> {
         let msg = format!("Expect {kind} name.");
         let name: Symbol = self.consume(TokenType::Identifier, &msg)?.into();
         let msg = format!("Expect `(` after {kind} name.");
         let _ = self.consume(TokenType::LeftParen, &msg)?;
+        let (params, rest) = self.parameters(kind)?;
 
-        let mut parameters = Vec::new();
+        let msg = format!("Expect `{{` before {kind} body.");
+        let _ = self.consume(TokenType::LeftBrace, &msg)?;
+        let body = self.function_body()?;
+
+        return Ok(FunDecl {
+            name,
+            params,
+            rest,
+            body,
+        });
+    }
+
+    fn lambda(&mut self) -> Result<Expr, Error> {
+        let keyword = self.previous();
+        let kind = FunctionKind::Lambda;
+        let msg = format!("Expect `(` after {kind} keyword.");
+        let _ = self.consume(TokenType::LeftParen, &msg)?;
+        let (params, rest) = self.parameters(kind)?;
+
+        let body = if self.match_one_of(vec![TokenType::Arrow]) {
+            let arrow = self.previous();
+            let value = self.expr()?;
+            vec![Stmt::new(
+                StmtKind::Return(arrow, Some(value)),
+                self.since(arrow),
+            )]
+        } else {
+            let msg = format!("Expect `{{` or `=>` before {kind} body.");
+            let _ = self.consume(TokenType::LeftBrace, &msg)?;
+            self.function_body()?
+        };
+
+        let kind = ExprKind::Lambda(Box::new(LambdaDecl {
+            keyword,
+            params,
+            rest,
+            body,
+        }));
+        return Ok(Expr::new(kind, self.since(keyword)));
+    }
+
+    /// Parses `a, b = 2, ...rest)`: plain parameters, then ones with defaults,
+    /// then an optional rest parameter.
+    fn parameters(&mut self, kind: FunctionKind) -> Result<(Vec<Param>, Option<Symbol>), Error> {
+        let mut parameters: Vec<Param> = Vec::new();
+        let mut rest = None;
         if !self.check_type(TokenType::RightParen) {
             loop {
                 if parameters.len() >= 255 {
-                    return Err(Error::TooManyArguments {
-                        line: self.peek().line,
-                        col: self.peek().col,
+                    return Err(Error::MaxParamsExceeded {
+                        kind,
+                        location: self.peek().location(),
                     });
                 }
 
-                parameters.push(
-                    self.consume(TokenType::Identifier, "Expect parameter name.")?
-                        .into(),
-                );
+                if self.match_one_of(vec![TokenType::Ellipsis]) {
+                    let msg = "Expect rest parameter name after `...`.";
+                    rest = Some(self.consume(TokenType::Identifier, msg)?.into());
+                    break;
+                }
+
+                let name: Symbol = self
+                    .consume(TokenType::Identifier, "Expect parameter name.")?
+                    .into();
+                let default = if self.match_one_of(vec![TokenType::Equal]) {
+                    Some(self.expr()?)
+                } else {
+                    None
+                };
+                let after_default = parameters.last().is_some_and(|p| p.default.is_some());
+                // Reported without unwinding: the parameter list itself is
+                // well formed, so parsing carries on with the body.
+                if default.is_none() && after_default {
+                    self.errors.push(Error::MissingDefault {
+                        name: name.name.clone(),
+                        location: name.location(),
+                    });
+                }
+                parameters.push(Param { name, default });
                 if !self.match_one_of(vec![TokenType::Comma]) {
                     break;
                 }
             }
         }
 
-        let _ = self.consume(TokenType::RightParen, "Expect `)` after parameters.");
-        let msg = format!("Expect `{{` before {kind} body.");
-        let _ = self.consume(TokenType::LeftBrace, &msg)?;
-        let body = self.block()?;
-        let mut vec_body = vec![];
-        match body {
-            Stmt::Block(v) => vec_body = v,
-            _ => {
-                return Err(Error::JustError {
-                    message: "Something went wrong".to_string(),
-                })
-            }
+        let msg = if rest.is_some() {
+            "Expect `)` after rest parameter."
+        } else {
+            "Expect `)` after parameters."
+        };
+        let _ = self.consume(TokenType::RightParen, msg)?;
+        return Ok((parameters, rest));
+    }
+
+    fn function_body(&mut self) -> Result<Vec<Stmt>, Error> {
+        // `break` and `continue` can't jump out of a function body.
+        let enclosing_loops = std::mem::take(&mut self.loops);
+        let body = self.block_body();
+        self.loops = enclosing_loops;
+        return body;
+    }
+
+    fn throw_statement(&mut self) -> Result<Stmt, Error> {
+        let keyword = self.previous();
+        let value = self.expr()?;
+        let _ = self.consume(TokenType::Semicolon, "Expect `;` after thrown value.")?;
+        return Ok(Stmt::new(
+            StmtKind::Throw(keyword, value),
+            self.since(keyword),
+        ));
+    }
+
+    fn try_statement(&mut self) -> Result<Stmt, Error> {
+        let start = self.previous();
+        let _ = self.consume(TokenType::LeftBrace, "Expect `{` after `try`.")?;
+        let body = self.block_body()?;
+
+        let mut catch = None;
+        if self.match_one_of(vec![TokenType::Catch]) {
+            let _ = self.consume(TokenType::LeftParen, "Expect `(` after `catch`.")?;
+            let name: Symbol = self
+                .consume(TokenType::Identifier, "Expect exception variable name.")?
+                .into();
+            let _ = self.consume(TokenType::RightParen, "Expect `)` after catch variable.")?;
+            let _ = self.consume(TokenType::LeftBrace, "Expect `{` before catch body.")?;
+            catch = Some((name, self.block_body()?));
+        }
+
+        let mut finally = None;
+        if self.match_one_of(vec![TokenType::Finally]) {
+            let _ = self.consume(TokenType::LeftBrace, "Expect `{` after `finally`.")?;
+            finally = Some(self.block_body()?);
+        }
+
+        if catch.is_none() && finally.is_none() {
+            return Err(Error::TokenMismatch {
+                expected: TokenType::Catch,
+                found: Box::new(self.peek()),
+                maybe_on_err_string: Some("Expect `catch` or `finally` after try block.".into()),
+            });
         }
-
-        let fun_decl = FunDecl {
-            name,
-            params: parameters,
-            body: vec_body,
+        let kind = StmtKind::Try {
+            body,
+            catch,
+            finally,
         };
-        return Ok(Stmt::FunDecl(fun_decl));
+        return Ok(Stmt::new(kind, self.since(start)));
+    }
+
+    fn block_body(&mut self) -> Result<Vec<Stmt>, Error> {
+        match self.block()?.kind {
+            StmtKind::Block(body) => Ok(body),
+            _ => Err(Error::JustError {
+                message: "Something went wrong".to_string(),
+            }),
+        }
     }
 
     fn return_statement(&mut self) -> Result<Stmt, Error> {
-        let keyword = &self.tokens[self.current - 1];
-        let source_location = SourceLocation {
-            line: keyword.line,
-            col: keyword.col,
-        };
+        let keyword = self.previous();
         let mut value = None;
         if !self.check_type(TokenType::Semicolon) {
             value = Some(self.expr()?);
         }
         let _ = self.consume(TokenType::Semicolon, "Expect `;` after return value.")?;
-        return Ok(Stmt::Return(source_location, value));
+        return Ok(Stmt::new(
+            StmtKind::Return(keyword, value),
+            self.since(keyword),
+        ));
     }
 }
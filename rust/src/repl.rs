@@ -0,0 +1,78 @@
+//! How lines typed at the prompt become inputs. Kept apart from the line
+//! editor so any source of lines can drive it.
+
+use crate::expr::FileId;
+use crate::scanner::{scan_tokens, TokenType};
+
+/// What a line read at the prompt amounts to.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Line {
+    /// A `:` command, typed at the start of an input.
+    Command(String),
+    /// A complete input: this line and the ones before it that were still
+    /// open, each ending in a newline.
+    Input(String),
+    /// The input still has open brackets, so reading goes on.
+    Incomplete,
+}
+
+/// Joins the lines read at the prompt into complete inputs.
+#[derive(Default)]
+pub struct LineBuffer {
+    input: String,
+}
+
+impl LineBuffer {
+    /// `> ` at the start of an input, `. ` while continuing one.
+    pub fn prompt(&self) -> &'static str {
+        if self.input.is_empty() {
+            return "> ";
+        }
+        return ". ";
+    }
+
+    pub fn push(&mut self, line: &str) -> Line {
+        if self.input.is_empty() && line.starts_with(':') {
+            return Line::Command(line.to_string());
+        }
+        self.input.push_str(line);
+        self.input.push('\n');
+        if is_incomplete(&self.input) {
+            return Line::Incomplete;
+        }
+        return Line::Input(std::mem::take(&mut self.input));
+    }
+
+    /// Drops the lines of the current input, e.g. on Ctrl-C.
+    pub fn clear(&mut self) {
+        self.input.clear();
+    }
+}
+
+/// Whether `input` has more opening brackets than closing ones, so the
+/// prompt should keep reading. Input that doesn't scan is complete; running
+/// it reports the error.
+pub fn is_incomplete(input: &str) -> bool {
+    let Ok(tokens) = scan_tokens(input.to_string(), FileId::default()) else {
+        return false;
+    };
+    let mut depth = 0;
+    for token in tokens {
+        match token.ty {
+            TokenType::LeftBrace | TokenType::LeftParen | TokenType::LeftBracket => depth += 1,
+            TokenType::RightBrace | TokenType::RightParen | TokenType::RightBracket => depth -= 1,
+            _ => {}
+        }
+    }
+    return depth > 0;
+}
+
+/// `input` with a semicolon added when it ends in a bare expression such as
+/// `1 + 2`, which the prompt accepts without one.
+pub fn terminate(input: &str) -> String {
+    let trimmed = input.trim_end();
+    if trimmed.ends_with([';', '}']) || trimmed.is_empty() {
+        return input.to_string();
+    }
+    return format!("{trimmed};\n");
+}
@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+
+use crate::{
+    error::Error,
+    expr::{ClassDecl, Expr, ExprKind, FunDecl, Param, SourceLocation, Stmt, StmtKind, Symbol},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FunctionType {
+    None,
+    Function,
+    Initializer,
+    Method,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ClassType {
+    None,
+    Class,
+    Subclass,
+}
+
+/// How many scopes away from each local variable use its declaration lives.
+/// Globals are left out.
+#[derive(Debug, Default)]
+pub struct Locals {
+    /// Uses inside function bodies, which can run again after the rest of
+    /// the program has finished.
+    pub in_functions: HashMap<Symbol, usize>,
+    /// Uses outside any function, only needed while the program runs.
+    pub top_level: HashMap<Symbol, usize>,
+}
+
+/// Walks the parsed program and records, for every local variable use, how many
+/// scopes away its declaration lives.
+pub fn resolve(stmts: &[Stmt]) -> Result<Locals, Vec<Error>> {
+    let mut resolver = Resolver::default();
+    resolver.resolve_stmts(stmts);
+
+    if resolver.errors.is_empty() {
+        return Ok(resolver.locals);
+    }
+    return Err(resolver.errors);
+}
+
+struct Resolver {
+    scopes: Vec<HashMap<String, bool>>,
+    locals: Locals,
+    errors: Vec<Error>,
+    current_function: FunctionType,
+    current_class: ClassType,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        return Self {
+            scopes: Vec::new(),
+            locals: Locals::default(),
+            errors: Vec::new(),
+            current_function: FunctionType::None,
+            current_class: ClassType::None,
+        };
+    }
+}
+
+impl Resolver {
+    fn resolve_stmts(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            self.resolve_stmt(stmt);
+        }
+    }
+
+    fn resolve_stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Expr(expr) | StmtKind::Print(expr) => self.resolve_expr(expr),
+            StmtKind::FunDecl(fun_decl) => {
+                self.declare(&fun_decl.name);
+                self.define(&fun_decl.name);
+                self.resolve_function(fun_decl, FunctionType::Function);
+            }
+            StmtKind::ClassDecl(class_decl) => self.resolve_class(class_decl),
+            StmtKind::If(condition, then_branch, else_branch) => {
+                self.resolve_expr(condition);
+                self.resolve_stmt(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.resolve_stmt(else_branch);
+                }
+            }
+            StmtKind::VarDecl(name, initializer) => {
+                self.declare(name);
+                if let Some(initializer) = initializer {
+                    self.resolve_expr(initializer);
+                }
+                self.define(name);
+            }
+            StmtKind::Block(stmts) => {
+                self.begin_scope();
+                self.resolve_stmts(stmts);
+                self.end_scope();
+            }
+            StmtKind::Return(location, value) => {
+                if self.current_function == FunctionType::None {
+                    self.errors.push(Error::ReturnNotInFun {
+                        location: *location,
+                    });
+                }
+                if let Some(value) = value {
+                    if self.current_function == FunctionType::Initializer {
+                        self.errors.push(Error::ReturnFromInitializer {
+                            location: *location,
+                        });
+                    }
+                    self.resolve_expr(value);
+                }
+            }
+            StmtKind::While {
+                condition,
+                body,
+                increment,
+                ..
+            } => {
+                self.resolve_expr(condition);
+                self.resolve_stmt(body);
+                if let Some(increment) = increment {
+                    self.resolve_expr(increment);
+                }
+            }
+            StmtKind::Break(..) | StmtKind::Continue(..) => {}
+            StmtKind::Throw(_, value) => self.resolve_expr(value),
+            StmtKind::Try {
+                body,
+                catch,
+                finally,
+            } => {
+                self.begin_scope();
+                self.resolve_stmts(body);
+                self.end_scope();
+                if let Some((name, catch_body)) = catch {
+                    self.begin_scope();
+                    self.declare(name);
+                    self.define(name);
+                    self.resolve_stmts(catch_body);
+                    self.end_scope();
+                }
+                if let Some(finally_body) = finally {
+                    self.begin_scope();
+                    self.resolve_stmts(finally_body);
+                    self.end_scope();
+                }
+            }
+        }
+    }
+
+    fn resolve_class(&mut self, class_decl: &ClassDecl) {
+        let enclosing_class = self.current_class;
+        self.current_class = ClassType::Class;
+
+        self.declare(&class_decl.name);
+        self.define(&class_decl.name);
+
+        if let Some(superclass) = &class_decl.superclass {
+            if superclass.name == class_decl.name.name {
+                self.errors.push(Error::InheritFromSelf {
+                    location: superclass.location(),
+                });
+            }
+            self.current_class = ClassType::Subclass;
+            self.resolve_local(superclass);
+
+            self.begin_scope();
+            self.scopes
+                .last_mut()
+                .unwrap()
+                .insert("super".to_string(), true);
+        }
+
+        self.begin_scope();
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert("this".to_string(), true);
+
+        for method in &class_decl.methods {
+            let function_type = if method.name.name == "init" {
+                FunctionType::Initializer
+            } else {
+                FunctionType::Method
+            };
+            self.resolve_function(method, function_type);
+        }
+
+        self.end_scope();
+        if class_decl.superclass.is_some() {
+            self.end_scope();
+        }
+
+        self.current_class = enclosing_class;
+    }
+
+    fn resolve_function(&mut self, fun_decl: &FunDecl, function_type: FunctionType) {
+        self.resolve_function_body(
+            &fun_decl.params,
+            fun_decl.rest.as_ref(),
+            &fun_decl.body,
+            function_type,
+        );
+    }
+
+    fn resolve_function_body(
+        &mut self,
+        params: &[Param],
+        rest: Option<&Symbol>,
+        body: &[Stmt],
+        function_type: FunctionType,
+    ) {
+        let enclosing_function = self.current_function;
+        self.current_function = function_type;
+
+        self.begin_scope();
+        for param in params {
+            // A default sees the parameters before it but not its own.
+            if let Some(default) = &param.default {
+                self.resolve_expr(default);
+            }
+            self.declare(&param.name);
+            self.define(&param.name);
+        }
+        if let Some(rest) = rest {
+            self.declare(rest);
+            self.define(rest);
+        }
+        self.resolve_stmts(body);
+        self.end_scope();
+
+        self.current_function = enclosing_function;
+    }
+
+    fn resolve_expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Literal(_) => {}
+            ExprKind::This(location) => {
+                if self.current_class == ClassType::None {
+                    self.errors.push(Error::ThisOutsideClass {
+                        location: *location,
+                    });
+                    return;
+                }
+                self.resolve_local(&keyword_symbol("this", location));
+            }
+            ExprKind::Super(location, _) => {
+                match self.current_class {
+                    ClassType::None => self.errors.push(Error::SuperOutsideClass {
+                        location: *location,
+                    }),
+                    ClassType::Class => self.errors.push(Error::SuperWithoutSuperclass {
+                        location: *location,
+                    }),
+                    ClassType::Subclass => {}
+                }
+                self.resolve_local(&keyword_symbol("super", location));
+            }
+            ExprKind::Unary(_, right) => self.resolve_expr(right),
+            ExprKind::Binary(left, _, right) | ExprKind::Logical(left, _, right) => {
+                self.resolve_expr(left);
+                self.resolve_expr(right);
+            }
+            ExprKind::Call(callee, _, args) => {
+                self.resolve_expr(callee);
+                for arg in args {
+                    self.resolve_expr(arg);
+                }
+            }
+            ExprKind::Get(object, _) => self.resolve_expr(object),
+            ExprKind::Set(object, _, value) => {
+                self.resolve_expr(value);
+                self.resolve_expr(object);
+            }
+            ExprKind::Grouping(expr) | ExprKind::Spread(expr) => self.resolve_expr(expr),
+            ExprKind::Variable(name) => {
+                if let Some(scope) = self.scopes.last() {
+                    if scope.get(&name.name) == Some(&false) {
+                        self.errors.push(Error::ReadInOwnInitializer {
+                            name: name.name.clone(),
+                            location: name.location(),
+                        });
+                    }
+                }
+                self.resolve_local(name);
+            }
+            ExprKind::Assign(name, value) => {
+                self.resolve_expr(value);
+                self.resolve_local(name);
+            }
+            ExprKind::List(elements) => {
+                for element in elements {
+                    self.resolve_expr(element);
+                }
+            }
+            ExprKind::Map(entries, _) => {
+                for (key, value) in entries {
+                    self.resolve_expr(key);
+                    self.resolve_expr(value);
+                }
+            }
+            ExprKind::Subscript { value, slice, .. } => {
+                self.resolve_expr(value);
+                self.resolve_expr(slice);
+            }
+            ExprKind::SetItem {
+                lhs, slice, rhs, ..
+            } => {
+                self.resolve_expr(lhs);
+                self.resolve_expr(slice);
+                self.resolve_expr(rhs);
+            }
+            ExprKind::Lambda(lambda_decl) => {
+                self.resolve_function_body(
+                    &lambda_decl.params,
+                    lambda_decl.rest.as_ref(),
+                    &lambda_decl.body,
+                    FunctionType::Function,
+                );
+            }
+        }
+    }
+
+    fn resolve_local(&mut self, name: &Symbol) {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if scope.contains_key(&name.name) {
+                let locals = match self.current_function {
+                    FunctionType::None => &mut self.locals.top_level,
+                    _ => &mut self.locals.in_functions,
+                };
+                locals.insert(name.clone(), depth);
+                return;
+            }
+        }
+    }
+
+    fn declare(&mut self, name: &Symbol) {
+        if let Some(scope) = self.scopes.last_mut() {
+            if scope.contains_key(&name.name) {
+                self.errors.push(Error::AlreadyDeclared {
+                    name: name.name.clone(),
+                    location: name.location(),
+                });
+            }
+            scope.insert(name.name.clone(), false);
+        }
+    }
+
+    fn define(&mut self, name: &Symbol) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.name.clone(), true);
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+}
+
+/// `this` and `super` are not declared through a token, so they are keyed by the
+/// location of the keyword, exactly as the interpreter looks them up.
+pub fn keyword_symbol(name: &str, location: &SourceLocation) -> Symbol {
+    return Symbol {
+        name: name.to_string(),
+        line: location.line,
+        col: location.col,
+        span: location.span,
+    };
+}
@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 use std::fmt;
 
-use crate::expr::Symbol;
+use crate::expr::{FileId, SourceLocation, Span, Symbol};
 
 #[derive(Eq, PartialEq, Debug, Copy, Clone)]
 pub enum TokenType {
@@ -13,6 +13,7 @@ pub enum TokenType {
     LeftBracket,
     RightBracket,
     Comma,
+    Colon,
     Dot,
     Minus,
     Plus,
@@ -29,6 +30,8 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    Arrow,
+    Ellipsis,
 
     // Literals.
     Identifier,
@@ -38,6 +41,11 @@ pub enum TokenType {
     // Keywords.
     // new
     Break,
+    Continue,
+    Try,
+    Catch,
+    Finally,
+    Throw,
     // end
     And,
     Class,
@@ -57,6 +65,8 @@ pub enum TokenType {
     While,
     Lambda,
 
+    /// A `//` comment. Only produced by `scan_tokens_with_comments`.
+    Comment,
     Eof,
 }
 
@@ -72,8 +82,20 @@ pub struct Token {
     pub ty: TokenType,
     pub lexeme: Vec<u8>,
     pub literal: Option<Literal>,
+    /// Line and 1-based column of the token's first character.
     pub line: usize,
     pub col: i64,
+    pub span: Span,
+}
+
+impl Token {
+    pub fn location(&self) -> SourceLocation {
+        return SourceLocation {
+            line: self.line,
+            col: self.col,
+            span: self.span,
+        };
+    }
 }
 
 impl fmt::Debug for Token {
@@ -90,18 +112,35 @@ impl fmt::Debug for Token {
     }
 }
 
-impl Into<Symbol> for Token {
-    fn into(self) -> Symbol {
+impl From<Token> for Symbol {
+    fn from(token: Token) -> Self {
         return Symbol {
-            name: String::from_utf8(self.lexeme).unwrap(),
-            line: self.line,
-            col: self.col,
+            name: String::from_utf8(token.lexeme).unwrap(),
+            line: token.line,
+            col: token.col,
+            span: token.span,
         };
     }
 }
 
-pub fn scan_tokens(input: String) -> Result<Vec<Token>, Error> {
-    let mut scanner: Scanner = Default::default();
+/// Splits `input` into tokens whose spans point into `file`.
+pub fn scan_tokens(input: String, file: FileId) -> Result<Vec<Token>, Error> {
+    return scan(input, file, false);
+}
+
+/// Like `scan_tokens`, but keeps comments as `Comment` tokens, for tools
+/// such as the formatter that must not lose them. The parser doesn't accept
+/// them.
+pub fn scan_tokens_with_comments(input: String, file: FileId) -> Result<Vec<Token>, Error> {
+    return scan(input, file, true);
+}
+
+fn scan(input: String, file: FileId, keep_comments: bool) -> Result<Vec<Token>, Error> {
+    let mut scanner = Scanner {
+        file,
+        keep_comments,
+        ..Default::default()
+    };
 
     scanner.scan_tokens(input);
 
@@ -120,12 +159,18 @@ pub struct Error {
 
 struct Scanner {
     source: Vec<u8>,
+    file: FileId,
+    keep_comments: bool,
     tokens: Vec<Token>,
     err: Option<Error>,
     start: usize,
     current: usize,
     line: usize,
+    /// 1-based column of the last character consumed.
     col: i64,
+    /// Where the token being scanned begins.
+    start_line: usize,
+    start_col: i64,
     keywords: HashMap<String, TokenType>,
 }
 
@@ -133,12 +178,16 @@ impl Default for Scanner {
     fn default() -> Scanner {
         Scanner {
             source: Vec::new(),
+            file: FileId::default(),
+            keep_comments: false,
             tokens: Vec::new(),
             err: None,
             start: 0,
             current: 0,
             line: 1,
-            col: -1,
+            col: 0,
+            start_line: 1,
+            start_col: 1,
             keywords: vec![
                 ("and", TokenType::And),
                 ("class", TokenType::Class),
@@ -158,6 +207,11 @@ impl Default for Scanner {
                 ("while", TokenType::While),
                 ("lambda", TokenType::Lambda),
                 ("break", TokenType::Break),
+                ("continue", TokenType::Continue),
+                ("try", TokenType::Try),
+                ("catch", TokenType::Catch),
+                ("finally", TokenType::Finally),
+                ("throw", TokenType::Throw),
             ]
             .into_iter()
             .map(|(k, v)| (String::from(k), v))
@@ -172,6 +226,8 @@ impl Scanner {
 
         while !self.done() {
             self.start = self.current;
+            self.start_line = self.line;
+            self.start_col = self.col + 1;
             self.scan_token();
         }
 
@@ -182,7 +238,8 @@ impl Scanner {
                 lexeme: Vec::new(),
                 literal: None,
                 line: self.line,
-                col: self.col,
+                col: self.col + 1,
+                span: self.span(self.current, self.current),
             }),
         }
     }
@@ -205,7 +262,16 @@ impl Scanner {
             '[' => self.add_token(TokenType::LeftBracket),
             ']' => self.add_token(TokenType::RightBracket),
             ',' => self.add_token(TokenType::Comma),
-            '.' => self.add_token(TokenType::Dot),
+            ':' => self.add_token(TokenType::Colon),
+            '.' => {
+                if self.peek() == '.' && self.peek_next() == '.' {
+                    self.advance();
+                    self.advance();
+                    self.add_token(TokenType::Ellipsis)
+                } else {
+                    self.add_token(TokenType::Dot)
+                }
+            }
             '-' => self.add_token(TokenType::Minus),
             '+' => self.add_token(TokenType::Plus),
             ';' => self.add_token(TokenType::Semicolon),
@@ -219,12 +285,16 @@ impl Scanner {
                 })
             }
             '=' => {
-                let matches_eq = self.matches('=');
-                self.add_token(if matches_eq {
-                    TokenType::EqualEqual
+                if self.matches('>') {
+                    self.add_token(TokenType::Arrow)
                 } else {
-                    TokenType::Equal
-                })
+                    let matches_eq = self.matches('=');
+                    self.add_token(if matches_eq {
+                        TokenType::EqualEqual
+                    } else {
+                        TokenType::Equal
+                    })
+                }
             }
             '<' => {
                 let matches_eq = self.matches('=');
@@ -247,6 +317,9 @@ impl Scanner {
                     while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
+                    if self.keep_comments {
+                        self.add_token(TokenType::Comment)
+                    }
                 } else {
                     self.add_token(TokenType::Slash)
                 }
@@ -265,8 +338,8 @@ impl Scanner {
                 } else {
                     self.err = Some(Error {
                         what: format!("scanner can't handle {}", c),
-                        line: self.line,
-                        col: self.col,
+                        line: self.start_line,
+                        col: self.start_col,
                     })
                 }
             }
@@ -330,22 +403,23 @@ impl Scanner {
 
     fn string(&mut self) {
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1
-            }
+            let newline = self.peek() == '\n';
             self.advance();
+            if newline {
+                self.line += 1;
+                self.col = 0;
+            }
         }
 
         if self.is_at_end() {
             self.err = Some(Error {
                 what: "Unterminated string".to_string(),
-                line: self.line,
-                col: self.col,
-            })
+                line: self.start_line,
+                col: self.start_col,
+            });
+            return;
         }
 
-        assert!(self.peek() == '"');
-
         self.advance();
 
         self.add_token_literal(
@@ -374,7 +448,7 @@ impl Scanner {
 
     fn matches(&mut self, c: char) -> bool {
         if self.is_at_end() {
-            return true;
+            return false;
         }
 
         if char::from(self.source[self.current]) != c {
@@ -397,11 +471,20 @@ impl Scanner {
             ty: token_type,
             lexeme: text,
             literal,
-            line: self.line,
-            col: self.col,
+            line: self.start_line,
+            col: self.start_col,
+            span: self.span(self.start, self.current),
         })
     }
 
+    fn span(&self, start: usize, end: usize) -> Span {
+        Span {
+            file: self.file,
+            start,
+            end,
+        }
+    }
+
     fn done(&self) -> bool {
         self.err.is_some() || self.is_at_end()
     }
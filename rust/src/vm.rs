@@ -0,0 +1,709 @@
+use std::{cell::RefCell, collections::HashMap, fmt, io::Write, rc::Rc};
+
+use crate::{
+    bytecode::{Constant, FunctionProto, OpCode},
+    debug,
+    env::Environment,
+    error::{Error, StackFrame},
+    expr::{
+        bind_method, Arity, BinaryOp, BinaryOpTy, LoxClass, LoxInstance, LoxMap, SourceLocation,
+    },
+    expr::{Symbol, UnaryOp, UnaryOpTy, Value},
+    interpreter::{
+        self, binary_op, check_arity, exception_value, get_item, is_truthy, set_item, spread,
+        unary_op, FRAMES_MAX,
+    },
+    natives::{self, NativeContext},
+    output::Output,
+};
+
+/// A captured variable. It points into the stack while the variable's scope
+/// is alive and holds the value itself once the scope has ended.
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+pub struct Closure {
+    pub function: Rc<FunctionProto>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    /// The instance a method was looked up on, passed in as `this`.
+    pub receiver: Option<Value>,
+}
+
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Closure({})", self.function.name)
+    }
+}
+
+impl Closure {
+    pub fn bind(&self, receiver: Value) -> Closure {
+        return Closure {
+            function: self.function.clone(),
+            upvalues: self.upvalues.clone(),
+            receiver: Some(receiver),
+        };
+    }
+}
+
+struct CallFrame {
+    closure: Rc<Closure>,
+    ip: usize,
+    /// Stack index of slot zero.
+    base: usize,
+    /// Set when the frame runs an initializer, so traces name the class.
+    class: Option<Rc<LoxClass>>,
+}
+
+impl CallFrame {
+    fn name(&self) -> &str {
+        match &self.class {
+            Some(class) => &class.name,
+            None => &self.closure.function.name,
+        }
+    }
+}
+
+struct Handler {
+    target: usize,
+    stack_len: usize,
+    frame_count: usize,
+}
+
+/// A stack machine that runs functions produced by `compiler::compile`.
+pub struct Vm {
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    globals: HashMap<String, Value>,
+    /// Upvalues still pointing into the stack, ordered by slot.
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    handlers: Vec<Handler>,
+    /// Errors caught by a handler that a `finally` block may raise again.
+    exceptions: Vec<Error>,
+    runtime_error_class: Rc<LoxClass>,
+    /// Print the stack and the next instruction before executing it.
+    pub trace: bool,
+    /// Set by the `exit` native. The call that ran it then fails with
+    /// `Error::Exit`.
+    exit_code: Option<u8>,
+    /// Where `print` and the trace write. Flushed whenever `interpret`
+    /// returns.
+    pub output: Output,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        let mut globals = Environment::default();
+        natives::define_natives(&mut globals);
+        return Self {
+            stack: Vec::new(),
+            frames: Vec::new(),
+            globals: globals.values,
+            open_upvalues: Vec::new(),
+            handlers: Vec::new(),
+            exceptions: Vec::new(),
+            runtime_error_class: interpreter::runtime_error_class(),
+            trace: false,
+            exit_code: None,
+            output: Output::default(),
+        };
+    }
+}
+
+impl NativeContext for Vm {
+    fn get_global(&self, name: &str) -> Option<Value> {
+        return self.globals.get(name).cloned();
+    }
+
+    fn set_global(&mut self, name: &str, value: Value) {
+        self.globals.insert(name.to_string(), value);
+    }
+
+    fn exit(&mut self, code: u8) {
+        self.exit_code = Some(code);
+    }
+}
+
+impl Vm {
+    /// Runs a compiled script and returns the value of its last statement
+    /// when that is an expression statement, or `nil` otherwise. Globals are
+    /// kept for the next script.
+    pub fn interpret(&mut self, function: Rc<FunctionProto>) -> Result<Value, Error> {
+        let closure = Rc::new(Closure {
+            function,
+            upvalues: Vec::new(),
+            receiver: None,
+        });
+        self.stack.push(Value::Closure(closure.clone()));
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            base: 0,
+            class: None,
+        });
+        let res = self.run();
+        self.stack.clear();
+        self.frames.clear();
+        self.open_upvalues.clear();
+        self.handlers.clear();
+        self.exceptions.clear();
+        return self.output.finish(res);
+    }
+
+    pub fn globals(&self) -> &HashMap<String, Value> {
+        return &self.globals;
+    }
+
+    fn run(&mut self) -> Result<Value, Error> {
+        loop {
+            match self.step() {
+                Ok(true) => return Ok(self.pop()),
+                // `exit` skips every handler, `finally` blocks included.
+                Err(Error::Exit { code }) => return Err(Error::Exit { code }),
+                Ok(false) => {}
+                Err(e) => self.throw(e)?,
+            }
+        }
+    }
+
+    fn frame(&self) -> &CallFrame {
+        return self.frames.last().unwrap();
+    }
+
+    fn function(&self) -> Rc<FunctionProto> {
+        return self.frame().closure.function.clone();
+    }
+
+    /// Location of the instruction being executed.
+    fn location(&self) -> SourceLocation {
+        let frame = self.frame();
+        return frame.closure.function.chunk.locations[frame.ip - 1];
+    }
+
+    fn name(&self, index: usize) -> String {
+        match &self.frame().closure.function.chunk.constants[index] {
+            Constant::String(name) => name.clone(),
+            constant => unreachable!("{constant:?} is not a name"),
+        }
+    }
+
+    fn symbol(&self, index: usize) -> Symbol {
+        let location = self.location();
+        return Symbol {
+            name: self.name(index),
+            line: location.line,
+            col: location.col,
+            span: location.span,
+        };
+    }
+
+    fn runtime_error(&self, message: String) -> Error {
+        let location = self.location();
+        return Error::RunTimeException { message, location };
+    }
+
+    fn pop(&mut self) -> Value {
+        return self.stack.pop().expect("stack underflow");
+    }
+
+    fn peek(&self, distance: usize) -> &Value {
+        return &self.stack[self.stack.len() - 1 - distance];
+    }
+
+    /// Runs one instruction, returning `true` once the script has returned.
+    fn step(&mut self) -> Result<bool, Error> {
+        if self.trace {
+            self.trace_instruction();
+        }
+        let frame = self.frames.last_mut().unwrap();
+        let op = frame.closure.function.chunk.code[frame.ip];
+        frame.ip += 1;
+        let base = frame.base;
+
+        match op {
+            OpCode::Constant(index) => {
+                let value = match &self.frame().closure.function.chunk.constants[index] {
+                    Constant::Number(n) => Value::Number(*n),
+                    Constant::String(s) => Value::String(s.clone()),
+                    Constant::Function(_) => unreachable!("functions are loaded by Closure"),
+                };
+                self.stack.push(value);
+            }
+            OpCode::Nil => self.stack.push(Value::Nil),
+            OpCode::True => self.stack.push(Value::Bool(true)),
+            OpCode::False => self.stack.push(Value::Bool(false)),
+            OpCode::Undefined => self.stack.push(Value::Undefined),
+            OpCode::Pop => {
+                self.pop();
+            }
+            OpCode::GetLocal { slot, name } => {
+                let value = self.stack[base + slot].clone();
+                self.push_defined(value, name)?;
+            }
+            OpCode::SetLocal(slot) => {
+                self.stack[base + slot] = self.peek(0).clone();
+            }
+            OpCode::GetGlobal(name) => {
+                let value = match self.globals.get(&self.name(name)) {
+                    Some(value) => value.clone(),
+                    None => return Err(self.undefined(name)),
+                };
+                self.push_defined(value, name)?;
+            }
+            OpCode::DefineGlobal(name) => {
+                let value = self.pop();
+                self.globals.insert(self.name(name), value);
+            }
+            OpCode::SetGlobal(name) => {
+                let value = self.peek(0).clone();
+                match self.globals.get_mut(&self.name(name)) {
+                    Some(slot) => *slot = value,
+                    None => return Err(self.undefined(name)),
+                }
+            }
+            OpCode::GetUpvalue { index, name } => {
+                let upvalue = self.frame().closure.upvalues[index].clone();
+                let value = match &*upvalue.borrow() {
+                    Upvalue::Open(slot) => self.stack[*slot].clone(),
+                    Upvalue::Closed(value) => value.clone(),
+                };
+                self.push_defined(value, name)?;
+            }
+            OpCode::SetUpvalue(index) => {
+                let value = self.peek(0).clone();
+                let upvalue = self.frame().closure.upvalues[index].clone();
+                let mut upvalue = upvalue.borrow_mut();
+                match &mut *upvalue {
+                    Upvalue::Open(slot) => self.stack[*slot] = value,
+                    Upvalue::Closed(closed) => *closed = value,
+                }
+            }
+            OpCode::GetProperty(name) => match self.pop() {
+                Value::Instance(instance) => {
+                    let value = LoxInstance::get(&instance, &self.symbol(name))?;
+                    self.stack.push(value);
+                }
+                _ => return Err(self.runtime_error("Only instances have properties.".to_string())),
+            },
+            OpCode::SetProperty(name) => {
+                let value = self.pop();
+                match self.pop() {
+                    Value::Instance(instance) => {
+                        instance.borrow_mut().set(&self.symbol(name), value.clone());
+                        self.stack.push(value);
+                    }
+                    _ => return Err(self.runtime_error("Only instances have fields.".to_string())),
+                }
+            }
+            OpCode::GetSuper(name) => {
+                let superclass = self.pop();
+                let instance = self.pop();
+                let (Value::Class(superclass), Value::Instance(instance)) = (superclass, instance)
+                else {
+                    return Err(self.runtime_error("`super` is not bound to a class.".to_string()));
+                };
+                match superclass.find_method(&self.name(name)) {
+                    Some(method) => self.stack.push(bind_method(method, instance)),
+                    None => {
+                        let message = format!("Undefined property `{0}`.", self.name(name));
+                        return Err(self.runtime_error(message));
+                    }
+                }
+            }
+            OpCode::GetIndex => {
+                let slice = self.pop();
+                let value = self.pop();
+                let value = get_item(value, slice, &self.location())?;
+                self.stack.push(value);
+            }
+            OpCode::SetIndex => {
+                let rhs = self.pop();
+                let slice = self.pop();
+                let lhs = self.pop();
+                let value = set_item(lhs, slice, rhs, &self.location())?;
+                self.stack.push(value);
+            }
+            OpCode::Equal => self.binary(BinaryOpTy::EqualEqual)?,
+            OpCode::NotEqual => self.binary(BinaryOpTy::NotEqual)?,
+            OpCode::Greater => self.binary(BinaryOpTy::Greater)?,
+            OpCode::GreaterEqual => self.binary(BinaryOpTy::GreaterEqual)?,
+            OpCode::Less => self.binary(BinaryOpTy::Less)?,
+            OpCode::LessEqual => self.binary(BinaryOpTy::LessEqual)?,
+            OpCode::Add => self.binary(BinaryOpTy::Plus)?,
+            OpCode::Subtract => self.binary(BinaryOpTy::Minus)?,
+            OpCode::Multiply => self.binary(BinaryOpTy::Star)?,
+            OpCode::Divide => self.binary(BinaryOpTy::Slash)?,
+            OpCode::Not => self.unary(UnaryOpTy::Bang)?,
+            OpCode::Negate => self.unary(UnaryOpTy::Minus)?,
+            OpCode::Print => {
+                let value = self.pop();
+                let location = self.location();
+                self.output.print(&value, location)?;
+            }
+            OpCode::Jump(target) => self.frames.last_mut().unwrap().ip = target,
+            OpCode::JumpIfFalse(target) => {
+                if !is_truthy(self.peek(0), &self.location())? {
+                    self.frames.last_mut().unwrap().ip = target;
+                }
+            }
+            OpCode::Call(arg_count) => {
+                let callee = self.peek(arg_count).clone();
+                self.call_value(callee, arg_count)?;
+            }
+            OpCode::CallSpread(parts) => {
+                let location = self.location();
+                let parts = self.stack.split_off(self.stack.len() - parts);
+                let mut arg_count = 0;
+                for part in parts {
+                    let arguments = spread(&part, &location)?;
+                    arg_count += arguments.len();
+                    self.stack.extend(arguments);
+                }
+                let callee = self.peek(arg_count).clone();
+                self.call_value(callee, arg_count)?;
+            }
+            OpCode::DefaultArg { slot, target } => {
+                if !matches!(self.stack[base + slot], Value::Undefined) {
+                    self.frames.last_mut().unwrap().ip = target;
+                }
+            }
+            OpCode::Closure(index) => {
+                let function = match &self.function().chunk.constants[index] {
+                    Constant::Function(function) => function.clone(),
+                    constant => unreachable!("{constant:?} is not a function"),
+                };
+                let upvalues = function
+                    .upvalues
+                    .iter()
+                    .map(|upvalue| match upvalue.is_local {
+                        true => self.capture_upvalue(base + upvalue.index),
+                        false => self.frame().closure.upvalues[upvalue.index].clone(),
+                    })
+                    .collect();
+                self.stack.push(Value::Closure(Rc::new(Closure {
+                    function,
+                    upvalues,
+                    receiver: None,
+                })));
+            }
+            OpCode::CloseUpvalue => {
+                self.close_upvalues(self.stack.len() - 1);
+                self.pop();
+            }
+            OpCode::Return => {
+                let result = self.pop();
+                let frame = self.frames.pop().unwrap();
+                self.close_upvalues(frame.base);
+                self.stack.truncate(frame.base);
+                let frame_count = self.frames.len();
+                self.handlers.retain(|h| h.frame_count <= frame_count);
+                self.stack.push(result);
+                if self.frames.is_empty() {
+                    return Ok(true);
+                }
+            }
+            OpCode::Class {
+                name,
+                methods,
+                superclass,
+            } => {
+                let closures = self.stack.split_off(self.stack.len() - methods);
+                let mut methods = HashMap::new();
+                for method in closures {
+                    // The compiler only emits closures here, but a `.loxc`
+                    // file may have been built by something else.
+                    let Value::Closure(closure) = &method else {
+                        let message = "Class methods must be functions.".to_string();
+                        return Err(self.runtime_error(message));
+                    };
+                    methods.insert(closure.function.name.clone(), method);
+                }
+                let superclass = match superclass {
+                    true => match self.peek(0) {
+                        Value::Class(superclass) => Some(superclass.clone()),
+                        _ => {
+                            let message = "Superclass must be a class.".to_string();
+                            return Err(self.runtime_error(message));
+                        }
+                    },
+                    false => None,
+                };
+                self.stack.push(Value::Class(Rc::new(LoxClass {
+                    name: self.name(name),
+                    superclass,
+                    methods,
+                })));
+            }
+            OpCode::List(count) => {
+                let values = self.stack.split_off(self.stack.len() - count);
+                self.stack.push(Value::List(Rc::new(RefCell::new(values))));
+            }
+            OpCode::Map(count) => {
+                let values = self.stack.split_off(self.stack.len() - 2 * count);
+                let location = self.location();
+                let mut map = LoxMap::default();
+                let mut values = values.into_iter();
+                while let (Some(key), Some(value)) = (values.next(), values.next()) {
+                    map.insert(interpreter::map_key(&key, &location)?, value);
+                }
+                self.stack.push(Value::Map(Rc::new(RefCell::new(map))));
+            }
+            OpCode::Throw => {
+                let value = self.pop();
+                let location = self.location();
+                return Err(Error::Throw { value, location });
+            }
+            OpCode::PushHandler(target) => {
+                self.handlers.push(Handler {
+                    target,
+                    stack_len: self.stack.len(),
+                    frame_count: self.frames.len(),
+                });
+            }
+            OpCode::PopHandler => {
+                self.handlers.pop();
+            }
+            OpCode::PopException => {
+                self.exceptions.pop();
+            }
+            OpCode::Rethrow => {
+                let error = self.exceptions.pop().expect("no exception to rethrow");
+                return Err(error);
+            }
+        }
+        return Ok(false);
+    }
+
+    fn trace_instruction(&mut self) {
+        let mut stack = String::from("          ");
+        for value in &self.stack {
+            stack.push_str(&format!("[ {value} ]"));
+        }
+        let frame = self.frame();
+        let instruction = debug::disassemble_instruction(&frame.closure.function.chunk, frame.ip);
+        // The trace is a debugging aid; losing some of it shouldn't stop the
+        // script.
+        let _ = write!(self.output, "{stack}\n{instruction}");
+    }
+
+    /// Pushes a variable's value, failing if it was declared without an
+    /// initializer and never assigned.
+    fn push_defined(&mut self, value: Value, name: usize) -> Result<(), Error> {
+        if let Value::Undefined = value {
+            return Err(self.undefined(name));
+        }
+        self.stack.push(value);
+        return Ok(());
+    }
+
+    fn undefined(&self, name: usize) -> Error {
+        let name = self.symbol(name);
+        return Error::UndefinedVariable {
+            location: name.location(),
+            name: name.name,
+        };
+    }
+
+    fn binary(&mut self, ty: BinaryOpTy) -> Result<(), Error> {
+        let right = self.pop();
+        let left = self.pop();
+        let location = self.location();
+        let op = BinaryOp {
+            ty,
+            line: location.line,
+            col: location.col,
+            span: location.span,
+        };
+        self.stack.push(binary_op(&op, left, right)?);
+        return Ok(());
+    }
+
+    fn unary(&mut self, ty: UnaryOpTy) -> Result<(), Error> {
+        let right = self.pop();
+        let location = self.location();
+        let op = UnaryOp {
+            ty,
+            line: location.line,
+            col: location.col,
+            span: location.span,
+        };
+        self.stack.push(unary_op(&op, right)?);
+        return Ok(());
+    }
+
+    fn call_value(&mut self, callee: Value, arg_count: usize) -> Result<(), Error> {
+        let callee_slot = self.stack.len() - arg_count - 1;
+        match callee {
+            Value::Closure(closure) => {
+                if let Some(receiver) = &closure.receiver {
+                    self.stack[callee_slot] = receiver.clone();
+                }
+                return self.call(closure, arg_count, None);
+            }
+            Value::NativeFunction(native) => {
+                check_arity(Arity::from(&native), arg_count, &self.location())?;
+                let arguments = self.stack.split_off(callee_slot + 1);
+                let result = (native.callable)(self, &arguments);
+                if let Some(code) = self.exit_code.take() {
+                    return Err(Error::Exit { code });
+                }
+                match result {
+                    Ok(value) => {
+                        self.stack.truncate(callee_slot);
+                        self.stack.push(value);
+                        return Ok(());
+                    }
+                    Err(message) => {
+                        let error = self.runtime_error(message);
+                        return Err(self.with_trace(error, Some(&native.name)));
+                    }
+                }
+            }
+            Value::Class(class) => {
+                let instance = Rc::new(RefCell::new(LoxInstance {
+                    class: class.clone(),
+                    fields: HashMap::new(),
+                }));
+                self.stack[callee_slot] = Value::Instance(instance);
+                match class.find_method("init") {
+                    Some(Value::Closure(initializer)) => {
+                        return self.call(initializer, arg_count, Some(class));
+                    }
+                    _ => {
+                        check_arity(Arity::exact(0), arg_count, &self.location())?;
+                        return Ok(());
+                    }
+                }
+            }
+            _ => {
+                return Err(self.runtime_error("Can only call function and classes".to_string()));
+            }
+        }
+    }
+
+    fn call(
+        &mut self,
+        closure: Rc<Closure>,
+        arg_count: usize,
+        class: Option<Rc<LoxClass>>,
+    ) -> Result<(), Error> {
+        if self.frames.len() >= FRAMES_MAX {
+            return Err(self.runtime_error("Stack overflow.".to_string()));
+        }
+        let function = closure.function.clone();
+        let arity = Arity {
+            min: function.arity - function.optional,
+            max: if function.rest {
+                None
+            } else {
+                Some(function.arity)
+            },
+        };
+        check_arity(arity, arg_count, &self.location())?;
+        // Parameters left out read as undefined until their defaults run.
+        for _ in arg_count..function.arity {
+            self.stack.push(Value::Undefined);
+        }
+        if function.rest {
+            let extra = arg_count.saturating_sub(function.arity);
+            let extra = self.stack.split_off(self.stack.len() - extra);
+            self.stack.push(Value::List(Rc::new(RefCell::new(extra))));
+        }
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            base: self.stack.len() - function.arity - function.rest as usize - 1,
+            class,
+        });
+        return Ok(());
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        for upvalue in self.open_upvalues.iter().rev() {
+            if let Upvalue::Open(open) = &*upvalue.borrow() {
+                if *open == slot {
+                    return upvalue.clone();
+                }
+                if *open < slot {
+                    break;
+                }
+            }
+        }
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        let position = self
+            .open_upvalues
+            .iter()
+            .position(|up| matches!(&*up.borrow(), Upvalue::Open(open) if *open > slot))
+            .unwrap_or(self.open_upvalues.len());
+        self.open_upvalues.insert(position, upvalue.clone());
+        return upvalue;
+    }
+
+    /// Moves every captured variable at or above `slot` off the stack.
+    fn close_upvalues(&mut self, slot: usize) {
+        while let Some(upvalue) = self.open_upvalues.last() {
+            let open = match &*upvalue.borrow() {
+                Upvalue::Open(open) => *open,
+                Upvalue::Closed(_) => unreachable!("closed upvalues are not tracked"),
+            };
+            if open < slot {
+                break;
+            }
+            let upvalue = self.open_upvalues.pop().unwrap();
+            *upvalue.borrow_mut() = Upvalue::Closed(self.stack[open].clone());
+        }
+    }
+
+    /// Unwinds to the innermost handler, or fails with the error if there is
+    /// none. Exceptions pick up a trace once they leave the frame that raised
+    /// them, matching the tree walker.
+    fn throw(&mut self, error: Error) -> Result<(), Error> {
+        let Some(handler) = self.handlers.pop() else {
+            return Err(self.with_trace(error, None));
+        };
+        let error = match handler.frame_count < self.frames.len() {
+            true => self.with_trace(error, None),
+            false => error,
+        };
+        self.close_upvalues(handler.stack_len);
+        self.frames.truncate(handler.frame_count);
+        self.stack.truncate(handler.stack_len);
+        let value = exception_value(&error, &self.runtime_error_class).unwrap_or(Value::Nil);
+        self.stack.push(value);
+        self.exceptions.push(error);
+        self.frames.last_mut().unwrap().ip = handler.target;
+        return Ok(());
+    }
+
+    /// Attaches the active calls to an exception, innermost first. A failing
+    /// native gets a frame of its own on top.
+    fn with_trace(&self, error: Error, native: Option<&str>) -> Error {
+        if let Error::Traced { .. } = error {
+            return error;
+        }
+        let line = error.line().unwrap_or(0);
+        let mut trace = Vec::with_capacity(self.frames.len() + 1);
+        if let Some(native) = native {
+            trace.push(StackFrame {
+                function: native.to_string(),
+                line,
+            });
+        }
+        for (i, frame) in self.frames.iter().rev().enumerate() {
+            let line = match i {
+                0 => line,
+                _ => frame.closure.function.chunk.locations[frame.ip - 1].line,
+            };
+            trace.push(StackFrame {
+                function: frame.name().to_string(),
+                line,
+            });
+        }
+        return Error::Traced {
+            error: Box::new(error),
+            trace,
+        };
+    }
+}
@@ -0,0 +1,145 @@
+#![allow(clippy::needless_return)]
+
+//! Runs the `rust_interpreter` binary the way a user would.
+
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+/// Runs the binary with `args`, feeding it `stdin`. History is kept out of
+/// the user's home directory.
+fn lox(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rust_interpreter"))
+        .args(args)
+        .env("HOME", env!("CARGO_TARGET_TMPDIR"))
+        .env("NO_COLOR", "1")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    return child.wait_with_output().unwrap();
+}
+
+fn stdout(output: &Output) -> String {
+    return String::from_utf8_lossy(&output.stdout).into_owned();
+}
+
+fn stderr(output: &Output) -> String {
+    return String::from_utf8_lossy(&output.stderr).into_owned();
+}
+
+#[test]
+fn prompt_keeps_state_and_joins_open_lines() {
+    let input = "var x = 1;\nfun add(a,\n  b) {\n  return a + b;\n}\nadd(x, 2)\nprint x;\n";
+    let output = lox(&[], input);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "3\n1\n");
+}
+
+#[test]
+fn prompt_survives_errors() {
+    let output = lox(&[], "print y;\nprint 1 +;\nprint 2;\n");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "2\n");
+    let errors = stderr(&output);
+    assert!(
+        errors.contains("error[E0302]: Undefined variable `y`"),
+        "{errors}"
+    );
+    assert!(errors.contains("error[E0106]"), "{errors}");
+}
+
+#[test]
+fn prompt_commands() {
+    let script = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("prompt_load.lox");
+    std::fs::write(&script, "var loaded = 41;\nprint loaded + 1;\n").unwrap();
+    let input = format!(
+        ":help\n:tokens 1 +\n:ast print 1;\n:load {}\n:env\n:reset\nprint loaded;\n",
+        script.display()
+    );
+    let output = lox(&[], &input);
+    let out = stdout(&output);
+    assert!(
+        out.contains(":load <file>    Run a script in this session"),
+        "{out}"
+    );
+    assert!(out.contains("Token { ty: Plus, lexeme: \"+\""), "{out}");
+    assert!(out.contains("Print @1:1"), "{out}");
+    assert!(out.contains("42\n"), "{out}");
+    assert!(out.contains("\"loaded\": Number(41.0)"), "{out}");
+    // `:reset` forgot what `:load` defined.
+    assert!(stderr(&output).contains("Undefined variable `loaded`"));
+}
+
+#[test]
+fn prompt_command_failures_go_to_stderr() {
+    let output = lox(&[], ":load no/such/file.lox\n:nope\n:time 1 + 1\n");
+    assert!(output.status.success());
+    let errors = stderr(&output);
+    assert!(
+        errors.contains("Failed to read `no/such/file.lox`"),
+        "{errors}"
+    );
+    assert!(
+        errors.contains("Unknown command `:nope`, see `:help`."),
+        "{errors}"
+    );
+    let out = stdout(&output);
+    assert!(out.starts_with("2\nTook "), "{out}");
+}
+
+/// Writes `source` to a script named `name` and returns its path.
+fn script(name: &str, source: &str) -> String {
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    std::fs::write(&path, source).unwrap();
+    return path.to_string_lossy().into_owned();
+}
+
+#[test]
+fn exit_statuses() {
+    let status = |args: &[&str]| lox(args, "").status.code();
+    assert_eq!(status(&["--no-such-flag"]), Some(64));
+    assert_eq!(status(&["run"]), Some(64));
+    assert_eq!(status(&["--help"]), Some(0));
+
+    let syntax = script("status_syntax.lox", "print 1 +;");
+    assert_eq!(status(&["run", &syntax]), Some(65));
+    assert_eq!(status(&["check", &syntax]), Some(65));
+
+    assert_eq!(status(&["run", "no/such/script.lox"]), Some(66));
+
+    let runtime = script("status_runtime.lox", "print nil + 1;");
+    assert_eq!(status(&["run", &runtime]), Some(70));
+    assert_eq!(status(&["--vm", "run", &runtime]), Some(70));
+
+    let exit = script("status_exit.lox", "print 1;\nexit(3);\nprint 2;");
+    assert_eq!(status(&["run", &exit]), Some(3));
+    assert_eq!(status(&["--vm", "run", &exit]), Some(3));
+    assert_eq!(lox(&[], "exit(4);\nprint 1;\n").status.code(), Some(4));
+
+    let ok = script("status_ok.lox", "print 1;");
+    assert_eq!(status(&["run", &ok]), Some(0));
+}
+
+#[test]
+fn scripts_see_their_arguments() {
+    let path = script("arguments.lox", "print args;\nprint len(args);");
+    let output = lox(&["run", &path, "one", "--two"], "");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "[one, --two]\n2\n");
+}
+
+#[test]
+fn file_flag_is_deprecated() {
+    let path = script("file_flag.lox", "print 1;");
+    let output = lox(&["--file", &path], "");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "1\n");
+    assert!(stderr(&output).contains("`--file` is deprecated"));
+}
@@ -0,0 +1,67 @@
+#![allow(clippy::needless_return)]
+
+use rust_interpreter::diagnostic::Diagnostic;
+use rust_interpreter::expr::Span;
+use rust_interpreter::Lox;
+
+/// The report for the error `source` stops with, without colours.
+fn report(source: &str) -> String {
+    let error = Lox::new().eval(source).unwrap_err();
+    return error.diagnostics()[0].render("script.lox", Some(source), false);
+}
+
+#[test]
+fn the_whole_span_is_underlined() {
+    let source = "var x = 1;\nx.field.other = 2;\n";
+    assert_eq!(
+        report(source),
+        "\
+error[E0301]: Only instances have properties.
+ --> script.lox:2:3
+  |
+2 | x.field.other = 2;
+  |   ^^^^^
+  |
+  = note: at <script> (line 2)
+"
+    );
+}
+
+#[test]
+fn underlines_line_up_after_non_ascii_text() {
+    let source = "{ var long = \"ü\" + long; }\n";
+    assert_eq!(
+        report(source),
+        "\
+error[E0201]: Can't read local variable `long` in its own initializer
+ --> script.lox:1:21
+  |
+1 | { var long = \"ü\" + long; }
+  |                    ^^^^
+"
+    );
+}
+
+#[test]
+fn a_column_inside_a_character_underlines_that_character() {
+    let diagnostic = Diagnostic {
+        code: "E0302",
+        message: "Undefined variable `y`".to_string(),
+        line: 1,
+        col: 9,
+        span: Span::default(),
+        label: None,
+        help: None,
+        notes: Vec::new(),
+    };
+    assert_eq!(
+        diagnostic.render("script.lox", Some("print \"ü\" + y;\n"), false),
+        "\
+error[E0302]: Undefined variable `y`
+ --> script.lox:1:9
+  |
+1 | print \"ü\" + y;
+  |        ^
+"
+    );
+}
@@ -0,0 +1,176 @@
+#![allow(clippy::needless_return)]
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use rust_interpreter::expr::FileId;
+use rust_interpreter::parser::LoxParser;
+use rust_interpreter::scanner::scan_tokens;
+use rust_interpreter::{resolver, Interpreter, Lox, LoxError, Output, Value};
+
+#[test]
+fn errors_display_with_their_location() {
+    let mut lox = Lox::new();
+    let error = lox.eval("var x = 1;\nprint x + y;").unwrap_err();
+    assert_eq!(error.to_string(), "[line 2:11] Undefined variable `y`");
+}
+
+#[test]
+fn parse_errors_display_one_per_line() {
+    let mut lox = Lox::new();
+    let error = lox.eval("print 1 +;\nvar = 2;").unwrap_err();
+    let message = error.to_string();
+    assert_eq!(message.lines().count(), 2, "{message}");
+    assert!(message.starts_with("[line 1:10] "), "{message}");
+}
+
+#[test]
+fn exit_is_reported_with_its_status() {
+    let mut lox = Lox::new();
+    let error = lox
+        .eval("fun stop() { exit(3); } try { stop(); } finally { print 1; }")
+        .unwrap_err();
+    assert!(matches!(error, LoxError::Exit(3)), "{error:?}");
+    assert!(matches!(
+        lox.call_function("stop", &[]),
+        Err(LoxError::Exit(3))
+    ));
+}
+
+#[test]
+fn missing_default_is_the_only_error() {
+    let mut lox = Lox::new();
+    let error = lox.eval("fun f(a = 1, b) { return b; }").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "[line 1:14] Parameter `b` needs a default value"
+    );
+}
+
+#[test]
+fn parsing_resumes_at_throw() {
+    let mut lox = Lox::new();
+    let error = lox.eval("var x = 1 + + 2\nthrow ;").unwrap_err();
+    assert_eq!(error.to_string().lines().count(), 2, "{error}");
+}
+
+#[test]
+fn repeated_frames_are_counted() {
+    let mut lox = Lox::new();
+    let source = "fun f(n) { if (n == 0) return nil + 1; return f(n - 1); }";
+    lox.eval(source).unwrap();
+    let mut trace = |depth: usize| {
+        let error = lox.eval(&format!("f({depth});")).unwrap_err();
+        return error.diagnostics()[0].notes.clone();
+    };
+    assert!(trace(3).contains(&"... 1 more frame".to_string()));
+    assert!(trace(4).contains(&"... 2 more frames".to_string()));
+}
+
+#[test]
+fn builder_natives_check_their_arity() {
+    let received = Rc::new(RefCell::new(Vec::new()));
+    let sink = received.clone();
+    let interpreter = Interpreter::builder()
+        .native("pair", 2, |_, args| Ok(Value::Number(args.len() as f64)))
+        .variadic_native("collect", 1, move |_, args| {
+            sink.borrow_mut().push(args.to_vec());
+            Ok(Value::Nil)
+        })
+        .build();
+    let mut lox = Lox::from(interpreter);
+
+    let message = |lox: &mut Lox, source: &str| lox.eval(source).unwrap_err().to_string();
+    assert_eq!(
+        message(&mut lox, "pair(1);"),
+        "[line 1:7] Expected 2 arguments but got 1."
+    );
+    assert_eq!(
+        message(&mut lox, "pair(1, 2, 3);"),
+        "[line 1:13] Expected 2 arguments but got 3."
+    );
+    assert_eq!(
+        message(&mut lox, "collect();"),
+        "[line 1:9] Expected at least 1 argument but got 0."
+    );
+    assert_eq!(lox.eval("pair(1, 2);").unwrap().to_string(), "2");
+
+    lox.eval("collect(1); collect(1, \"two\", nil, ...[4, 5]);")
+        .unwrap();
+    let received: Vec<Vec<String>> = received
+        .borrow()
+        .iter()
+        .map(|args| args.iter().map(Value::to_string).collect())
+        .collect();
+    assert_eq!(received, [vec!["1"], vec!["1", "two", "nil", "4", "5"]]);
+}
+
+#[test]
+fn print_writes_to_a_buffer_output() {
+    let interpreter = Interpreter::builder().output(Output::buffer()).build();
+    let mut lox = Lox::from(interpreter);
+    lox.eval("print 1 + 2; print nil; print [1, \"a\", true];")
+        .unwrap();
+    assert_eq!(
+        lox.output().contents(),
+        Some(&b"3\nnil\n[1, a, true]\n"[..])
+    );
+    assert_eq!(lox.output().take(), b"3\nnil\n[1, a, true]\n");
+
+    // What a failing script printed before the error is kept too.
+    lox.eval("print \"before\"; print nil + 1;").unwrap_err();
+    assert_eq!(lox.output().take(), b"before\n");
+}
+
+/// A writer whose contents the test can still read once the interpreter
+/// owns it.
+#[derive(Clone, Default)]
+struct Shared(Rc<RefCell<Vec<u8>>>);
+
+impl std::io::Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        return Ok(());
+    }
+}
+
+#[test]
+fn print_writes_to_a_writer_output() {
+    let shared = Shared::default();
+    let interpreter = Interpreter::builder()
+        .output(Output::Writer(Box::new(shared.clone())))
+        .build();
+    let mut lox = Lox::from(interpreter);
+    lox.eval("fun greet(name) { print \"hi \" + name; } greet(\"lox\");")
+        .unwrap();
+    assert_eq!(shared.0.borrow().as_slice(), b"hi lox\n");
+    assert_eq!(lox.output().contents(), None);
+}
+
+#[test]
+fn top_level_locals_are_dropped_after_each_run() {
+    let mut interpreter = Interpreter::builder().output(Output::buffer()).build();
+    let run = |interpreter: &mut Interpreter, file: u32, source: &str| {
+        let tokens = scan_tokens(source.to_string(), FileId(file)).unwrap();
+        let (stmts, errors) = LoxParser::from_tokens(tokens).parse();
+        assert!(errors.is_empty(), "{errors:?}");
+        interpreter.resolve(resolver::resolve(&stmts).unwrap());
+        interpreter.interpret(&stmts).unwrap();
+    };
+    run(
+        &mut interpreter,
+        0,
+        "fun add(a) { var b = 1; return a + b; }",
+    );
+    let kept = interpreter.locals.len();
+    assert!(kept > 0);
+    for file in 1..4 {
+        run(&mut interpreter, file, "{ var x = 2; print add(x); }");
+        assert_eq!(interpreter.locals.len(), kept);
+    }
+    assert_eq!(interpreter.output.take(), b"3\n3\n3\n");
+}
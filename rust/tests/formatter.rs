@@ -0,0 +1,50 @@
+#![allow(clippy::needless_return)]
+
+use rust_interpreter::expr::FileId;
+use rust_interpreter::formatter;
+use rust_interpreter::scanner::scan_tokens_with_comments;
+
+fn format(source: &str) -> String {
+    let tokens = scan_tokens_with_comments(source.to_string(), FileId::default()).unwrap();
+    return formatter::format(&tokens);
+}
+
+const MESSY: &str = r#"// Header.
+var x=-1;   // trailing
+
+
+fun f(a, b = 2, ...rest){
+  // inside
+  return a-b*-x + len(rest);}
+print f(1, ...[3,4]);
+var m = {"k": {}, "l": [-1, - 2]};
+if (x < 0) { print "neg"; } else { for (var i = 0; i < 2; i = i + 1) print i; }
+fun g() {}
+"#;
+
+const FORMATTED: &str = r#"// Header.
+var x = -1; // trailing
+
+fun f(a, b = 2, ...rest) {
+    // inside
+    return a - b * -x + len(rest);
+}
+print f(1, ...[3, 4]);
+var m = {"k": {}, "l": [-1, -2]};
+if (x < 0) {
+    print "neg";
+} else {
+    for (var i = 0; i < 2; i = i + 1) print i;
+}
+fun g() {}
+"#;
+
+#[test]
+fn formats_and_keeps_comments() {
+    assert_eq!(format(MESSY), FORMATTED);
+}
+
+#[test]
+fn formatting_twice_changes_nothing() {
+    assert_eq!(format(FORMATTED), FORMATTED);
+}
@@ -0,0 +1,73 @@
+#![allow(clippy::needless_return)]
+
+//! Damaged `.loxc` files must be rejected when loaded rather than crash the
+//! VM.
+
+use rust_interpreter::bytecode::{FunctionProto, OpCode};
+use rust_interpreter::expr::FileId;
+use rust_interpreter::parser::LoxParser;
+use rust_interpreter::scanner::scan_tokens;
+use rust_interpreter::{compiler, loxc, Error};
+
+fn compile(source: &str) -> FunctionProto {
+    let tokens = scan_tokens(source.to_string(), FileId::default()).unwrap();
+    let (stmts, errors) = LoxParser::from_tokens(tokens).parse();
+    assert!(errors.is_empty(), "{errors:?}");
+    return compiler::compile(&stmts).unwrap().as_ref().clone();
+}
+
+/// Saves `function` after `damage` has been done to it and loads it back.
+fn load_damaged(source: &str, damage: impl FnOnce(&mut FunctionProto)) -> Error {
+    let mut function = compile(source);
+    damage(&mut function);
+    return loxc::load(&loxc::save(&function)).unwrap_err();
+}
+
+fn replace(function: &mut FunctionProto, find: fn(&OpCode) -> bool, op: OpCode) {
+    let index = function.chunk.code.iter().position(find).unwrap();
+    function.chunk.code[index] = op;
+}
+
+#[test]
+fn round_trip() {
+    let function = compile("fun f(a, b = 1) { return a + b; } print f(2);");
+    assert!(loxc::load(&loxc::save(&function)).is_ok());
+}
+
+#[test]
+fn rejects_local_outside_frame() {
+    let error = load_damaged("{ var b = 1; b = 2; }", |function| {
+        replace(
+            function,
+            |op| matches!(op, OpCode::SetLocal(_)),
+            OpCode::SetLocal(99),
+        );
+    });
+    assert!(matches!(error, Error::InvalidLoxc { .. }), "{error:?}");
+}
+
+#[test]
+fn rejects_missing_upvalue() {
+    let error = load_damaged("fun f() {}", |function| {
+        replace(
+            function,
+            |op| matches!(op, OpCode::Closure(_)),
+            OpCode::SetUpvalue(0),
+        );
+    });
+    assert!(matches!(error, Error::InvalidLoxc { .. }), "{error:?}");
+}
+
+#[test]
+fn rejects_stack_underflow() {
+    let error = load_damaged("print 1;", |function| {
+        replace(function, |op| matches!(op, OpCode::Print), OpCode::List(5));
+    });
+    assert!(matches!(error, Error::InvalidLoxc { .. }), "{error:?}");
+}
+
+#[test]
+fn rejects_more_optional_than_parameters() {
+    let error = load_damaged("", |function| function.optional = 3);
+    assert!(matches!(error, Error::InvalidLoxc { .. }), "{error:?}");
+}
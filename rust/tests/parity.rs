@@ -0,0 +1,66 @@
+#![allow(clippy::needless_return)]
+
+//! Runs every script in `tests/parity` on the tree-walking interpreter and on
+//! the VM, and checks that both print the same output and report the same
+//! error.
+
+use std::fs;
+use std::path::Path;
+
+use rust_interpreter::diagnostic::Diagnostic;
+use rust_interpreter::expr::FileId;
+use rust_interpreter::parser::LoxParser;
+use rust_interpreter::scanner::scan_tokens;
+use rust_interpreter::vm::Vm;
+use rust_interpreter::{compiler, resolver, Interpreter, Lox, Output};
+
+/// What a script printed, followed by the report for the error that stopped
+/// it, if any.
+fn tree_walker(source: &str) -> String {
+    let interpreter = Interpreter::builder().output(Output::buffer()).build();
+    let mut lox = Lox::from(interpreter);
+    let result = lox.eval(source);
+    let mut out = String::from_utf8(lox.output().take()).unwrap();
+    if let Err(e) = result {
+        for diagnostic in e.diagnostics() {
+            out.push_str(&diagnostic.render("script", Some(source), false));
+        }
+    }
+    return out;
+}
+
+fn vm(source: &str) -> String {
+    let tokens = scan_tokens(source.to_string(), FileId::default()).unwrap();
+    let (stmts, errors) = LoxParser::from_tokens(tokens).parse();
+    assert!(errors.is_empty(), "{errors:?}");
+    resolver::resolve(&stmts).unwrap();
+    let function = compiler::compile(&stmts).unwrap();
+    let mut vm = Vm::default();
+    vm.output = Output::buffer();
+    let result = vm.interpret(function);
+    let mut out = String::from_utf8(vm.output.take()).unwrap();
+    if let Err(e) = result {
+        out.push_str(&Diagnostic::from(&e).render("script", Some(source), false));
+    }
+    return out;
+}
+
+#[test]
+fn backends_agree() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/parity");
+    let mut scripts: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    scripts.sort();
+    assert!(!scripts.is_empty());
+    for script in scripts {
+        let source = fs::read_to_string(&script).unwrap();
+        assert_eq!(
+            tree_walker(&source),
+            vm(&source),
+            "{} behaves differently on the VM",
+            script.display()
+        );
+    }
+}
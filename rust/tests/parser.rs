@@ -0,0 +1,43 @@
+#![allow(clippy::needless_return)]
+
+use rust_interpreter::diagnostic::Diagnostic;
+use rust_interpreter::expr::FileId;
+use rust_interpreter::parser::LoxParser;
+use rust_interpreter::scanner::scan_tokens;
+
+/// Every syntax error in `source`, as `code line:col message`.
+fn parse_errors(source: &str) -> Vec<String> {
+    let tokens = scan_tokens(source.to_string(), FileId::default()).unwrap();
+    let (_, errors) = LoxParser::from_tokens(tokens).parse();
+    return errors
+        .iter()
+        .map(Diagnostic::from)
+        .map(|d| format!("{} {}:{} {}", d.code, d.line, d.col, d.message))
+        .collect();
+}
+
+#[test]
+fn recovery_in_a_block_stops_at_its_closing_brace() {
+    let source = "{ print 1 + }\nprint 2;\nvar = 3;\nfun f( { }\nprint 4\nvar y = 5;";
+    assert_eq!(
+        parse_errors(source),
+        [
+            "E0106 1:13 Expected an expression, found RightBrace",
+            "E0102 3:5 Expect variable name",
+            "E0102 4:8 Expect parameter name.",
+            "E0102 6:1 Expect `;` after value",
+        ]
+    );
+}
+
+#[test]
+fn recovery_skips_braces_opened_inside_a_block() {
+    let source = "{\n  fun f( { }\n  print 1;\n}\nprint 2 +;";
+    assert_eq!(
+        parse_errors(source),
+        [
+            "E0102 2:10 Expect parameter name.",
+            "E0106 5:10 Expected an expression, found Semicolon",
+        ]
+    );
+}
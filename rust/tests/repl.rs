@@ -0,0 +1,56 @@
+#![allow(clippy::needless_return)]
+
+use rust_interpreter::repl::{self, Line, LineBuffer};
+
+#[test]
+fn open_brackets_continue_on_the_next_line() {
+    let mut lines = LineBuffer::default();
+    assert_eq!(lines.prompt(), "> ");
+    assert_eq!(lines.push("fun f(a) {"), Line::Incomplete);
+    assert_eq!(lines.prompt(), ". ");
+    assert_eq!(lines.push("  return [a,"), Line::Incomplete);
+    assert_eq!(lines.push("  (a)];"), Line::Incomplete);
+    assert_eq!(
+        lines.push("}"),
+        Line::Input("fun f(a) {\n  return [a,\n  (a)];\n}\n".to_string())
+    );
+    assert_eq!(lines.prompt(), "> ");
+}
+
+#[test]
+fn commands_only_start_an_input() {
+    let mut lines = LineBuffer::default();
+    assert_eq!(lines.push(":env"), Line::Command(":env".to_string()));
+    assert_eq!(lines.push("print ("), Line::Incomplete);
+    assert_eq!(
+        lines.push(":env)"),
+        Line::Input("print (\n:env)\n".to_string())
+    );
+}
+
+#[test]
+fn clearing_abandons_the_input() {
+    let mut lines = LineBuffer::default();
+    assert_eq!(lines.push("{"), Line::Incomplete);
+    lines.clear();
+    assert_eq!(lines.prompt(), "> ");
+    assert_eq!(
+        lines.push("print 1;"),
+        Line::Input("print 1;\n".to_string())
+    );
+}
+
+#[test]
+fn unscannable_input_is_complete() {
+    assert!(repl::is_incomplete("{ ("));
+    assert!(!repl::is_incomplete("{ \"unterminated"));
+    assert!(!repl::is_incomplete("print \"{\";"));
+}
+
+#[test]
+fn bare_expressions_get_a_semicolon() {
+    assert_eq!(repl::terminate("1 + 2\n"), "1 + 2;\n");
+    assert_eq!(repl::terminate("print 1;\n"), "print 1;\n");
+    assert_eq!(repl::terminate("{ print 1; }\n"), "{ print 1; }\n");
+    assert_eq!(repl::terminate("\n"), "\n");
+}
@@ -0,0 +1,102 @@
+#![allow(clippy::needless_return)]
+
+use rust_interpreter::diagnostic::Diagnostic;
+use rust_interpreter::expr::FileId;
+use rust_interpreter::parser::LoxParser;
+use rust_interpreter::resolver;
+use rust_interpreter::scanner::scan_tokens;
+
+/// Every static error in `source`, as `code line:col message`.
+fn resolve_errors(source: &str) -> Vec<String> {
+    let tokens = scan_tokens(source.to_string(), FileId::default()).unwrap();
+    let (stmts, errors) = LoxParser::from_tokens(tokens).parse();
+    assert!(errors.is_empty(), "{errors:?}");
+    let Err(errors) = resolver::resolve(&stmts) else {
+        return Vec::new();
+    };
+    return errors
+        .iter()
+        .map(Diagnostic::from)
+        .map(|d| format!("{} {}:{} {}", d.code, d.line, d.col, d.message))
+        .collect();
+}
+
+#[test]
+fn read_in_own_initializer() {
+    assert_eq!(
+        resolve_errors("var a = 1;\n{ var a = a; }"),
+        ["E0201 2:11 Can't read local variable `a` in its own initializer"]
+    );
+    assert!(resolve_errors("var a = 1;\nvar a = a;").is_empty());
+}
+
+#[test]
+fn redeclared_in_the_same_scope() {
+    assert_eq!(
+        resolve_errors("{\n  var a = 1;\n  var a = 2;\n}"),
+        ["E0202 3:7 Already a variable named `a` in this scope"]
+    );
+    assert!(resolve_errors("{ var a = 1; { var a = 2; } }").is_empty());
+}
+
+#[test]
+fn return_at_top_level() {
+    assert_eq!(
+        resolve_errors("print 1;\nreturn 2;"),
+        ["E0109 2:1 Can't return from top-level code"]
+    );
+}
+
+#[test]
+fn return_value_from_initializer() {
+    assert_eq!(
+        resolve_errors("class A {\n  init() { return 1; }\n}"),
+        ["E0203 2:12 Can't return a value from an initializer"]
+    );
+    assert!(resolve_errors("class A { init() { return; } }").is_empty());
+}
+
+#[test]
+fn this_outside_a_class() {
+    assert_eq!(
+        resolve_errors("fun f() { return this; }"),
+        ["E0204 1:18 Can't use `this` outside of a class"]
+    );
+}
+
+#[test]
+fn super_outside_a_class() {
+    assert_eq!(
+        resolve_errors("print super.m;"),
+        ["E0205 1:7 Can't use `super` outside of a class"]
+    );
+}
+
+#[test]
+fn super_without_a_superclass() {
+    assert_eq!(
+        resolve_errors("class A { m() { return super.m(); } }"),
+        ["E0206 1:24 Can't use `super` in a class with no superclass"]
+    );
+}
+
+#[test]
+fn inherit_from_self() {
+    assert_eq!(
+        resolve_errors("class A < A {}"),
+        ["E0207 1:11 A class can't inherit from itself"]
+    );
+}
+
+#[test]
+fn every_error_is_reported() {
+    let source = "return 1;\n{ var a = 1; var a = 2; }\nprint this;";
+    assert_eq!(
+        resolve_errors(source),
+        [
+            "E0109 1:1 Can't return from top-level code",
+            "E0202 2:18 Already a variable named `a` in this scope",
+            "E0204 3:7 Can't use `this` outside of a class",
+        ]
+    );
+}